@@ -1,23 +1,57 @@
-use crate::consts::{CREATE_A_PROPOSAL, MAIN_MENU, SEE_PROPOSALS};
+use crate::consts::{
+    CREATE_A_PROPOSAL, MAIN_MENU, MAX_CATEGORY_LENGTH, PROPOSAL_LINK_PREFIX, SEE_PROPOSALS,
+};
+use crate::dates::{chat_timezone, parse_timezone};
 use crate::handler::callback_handlers::{
-    handle_menu_callback, handle_new_proposal_callback, handle_proposal_fields_callback,
-    handle_see_proposals_callback, handle_submit_proposal_callback, handle_thumb_up_callback,
+    handle_add_to_calendar_callback, handle_browse_callback, handle_calendar_callback,
+    handle_cancel_veto_callback, handle_close_draft_callback, handle_close_voting_callback,
+    handle_comment_callback, handle_discuss_callback, handle_filter_proposals_callback,
+    handle_menu_callback, handle_new_proposal_callback, handle_open_proposal_callback,
+    handle_proposal_fields_callback, handle_search_page_callback, handle_see_proposals_callback,
+    handle_share_callback, handle_sort_callback, handle_sponsor_callback,
+    handle_submit_proposal_callback, handle_template_callback, handle_veto_callback,
+    handle_vote_callback, send_search_results,
 };
+use crate::handler::chat_member_handlers::{chat_member_handler, my_chat_member_handler};
 use crate::handler::dialogue_handlers::{
-    receive_description_handler, receive_expiration_date_handler, receive_starting_date_handler,
-    receive_title_handler, start_title_dialogue_handler, DialogueState,
+    propose_command, receive_comment_handler, receive_comment_reply_handler, receive_field_handler,
+    receive_veto_reason_handler, start_title_dialogue_handler, DialogueState,
+};
+use crate::handler::inline_handlers::inline_query_handler;
+use crate::handler::poll_handlers::{poll_answer_handler, poll_handler};
+use crate::handler::{
+    find_pending_veto, find_replied_proposal_id, match_sub_menu, send_linked_proposal, SubMenuType,
+};
+use crate::ical::proposals_calendar;
+use crate::keyboards::calendar_keyboard::CalendarKeyboard;
+use crate::keyboards::see_proposals_keyboard::{new_see_proporsal_keyboard, SeeProposalsKeyboard};
+use crate::recurring::RecurringProposal;
+use crate::scheduler::run_scheduler;
+use crate::storage::{
+    Proposal, ProposalStatus, TgChatSettingsStorage, TgCommentStorage, TgMessage, TgMessageStorage,
+    TgProposalStorage, TgRecurringStorage, TgTemplateStorage, TgUserSettingsStorage,
+    GLOBAL_CHAT_SETTINGS_STORAGE, GLOBAL_COMMENT_STORAGE, GLOBAL_MAIN_MENU_STORAGE,
+    GLOBAL_PROPOSAL_STORAGE, GLOBAL_RECURRING_STORAGE, GLOBAL_TEMPLATE_STORAGE,
+    GLOBAL_USER_SETTINGS_STORAGE,
+};
+use crate::templates::ProposalTemplate;
+use crate::utils::{
+    delete_previous_messages, in_member_chats, is_chat_admin, is_chat_member, is_sender_admin,
+    resolve_channel, topic, user_name, InTopic,
 };
-use crate::handler::{match_sub_menu, SubMenuType};
-use crate::keyboards::see_proposals_keyboard::SeeProposalsKeyboard;
-use crate::storage::{TgMessage, TgMessageStorage, GLOBAL_MAIN_MENU_STORAGE};
-use crate::utils::delete_previous_messages;
+use crate::webhook::{unregister_webhook, webhook_listener, WebhookConfig};
 use crate::TgError;
 use crate::{
     keyboards::{create_new_proposal_keyboard::CreateNewProposalKeyboard, menu_keyboard},
-    messages::get_welcome_message,
+    messages::{
+        get_detailed_results_message, get_proposal_list_message, get_proposal_message,
+        get_welcome_message, parse_tags,
+    },
 };
+use chrono::Utc;
 use core::time::Duration;
 use dotenv::dotenv;
+use std::cmp::Reverse;
 use std::env;
 use std::sync::Arc;
 use teloxide::{
@@ -26,7 +60,7 @@ use teloxide::{
     error_handlers::LoggingErrorHandler,
     payloads::SendMessageSetters,
     prelude::{Dispatcher, Requester},
-    types::{CallbackQuery, Message, ParseMode, Update},
+    types::{CallbackQuery, InputFile, Message, ParseMode, Update, UserId},
     utils::command::BotCommands,
     Bot,
 };
@@ -40,12 +74,74 @@ enum Command {
     #[command(description = "Main Menu")]
     Menu,
     #[command(description = "Start the bot")]
-    Start,
+    Start(String),
+    #[command(description = "Set how many co-sponsors a proposal needs before voting opens")]
+    Sponsors(String),
+    #[command(description = "Set the chat's proposal categories, separated by commas")]
+    Categories(String),
+    #[command(
+        description = "List the chat's proposal templates, or define one: the name on the first line, then one \"Field: type [required] [/pattern/]\" per line. Types are text, number, url, date and choice(a, b)"
+    )]
+    Template(String),
+    #[command(description = "Remove one of the chat's proposal templates")]
+    RemoveTemplate(String),
+    #[command(
+        description = "Draft a proposal in one go: /propose Title | Description | start | end. Parts left out are asked for afterwards"
+    )]
+    Propose(String),
+    #[command(
+        description = "Show or set the chat's time zone, e.g. /timezone Europe/Berlin. Dates are read and shown in it"
+    )]
+    Timezone(String),
+    #[command(
+        description = "Show or set your own time zone for your private chat with the bot, /mytimezone off to follow the chat's"
+    )]
+    MyTimezone(String),
+    #[command(
+        description = "Show or set pinning: /pin on, /pin silent or /pin off pins the cards of active proposals, /pin results <hours> keeps results pinned that long (0 to not pin them)"
+    )]
+    Pin(String),
+    #[command(
+        description = "Show or set the channel new proposals and results are cross-posted to, e.g. /channel @announcements, or /channel off"
+    )]
+    Channel(String),
+    #[command(
+        description = "Show or set how many days members have to be in the chat before they can vote or propose, e.g. /memberage 7, or /memberage 0. Needs the bot to be an admin"
+    )]
+    MemberAge(String),
+    #[command(description = "Open a proposal by its number, e.g. /proposal 12")]
+    Proposal(String),
+    #[command(
+        description = "Show the detailed results of a closed proposal: the outcome, the vote split, sponsors and what happened to it, e.g. /results 12"
+    )]
+    Results(String),
+    #[command(
+        description = "Search proposals by words in their title, description, tags and comments, best matches first. Narrow it down with status:passed (or sponsors, active, rejected, vetoed), from:<date> and to:<date>, e.g. /search budget status:passed from:2024-01-01"
+    )]
+    Search(String),
+    #[command(
+        description = "List the proposals you voted on and how, in this chat or in all your chats when asked in private"
+    )]
+    MyVotes,
+    #[command(
+        description = "List the proposals you submitted and where they stand, in this chat or in all your chats when asked in private"
+    )]
+    MyProposals,
+    #[command(description = "Export the chat's upcoming voting windows as a calendar file")]
+    Calendar,
+    #[command(
+        description = "List the chat's recurring proposals, or define one: the title on the first line, then \"Schedule: <minute hour day month weekday>\", an optional \"Voting: 7 days\" and \"Field: value\" lines to copy into every proposal"
+    )]
+    Recurring(String),
+    #[command(description = "Stop one of the chat's recurring proposals")]
+    RemoveRecurring(String),
 }
 
 #[derive(Clone, Debug)]
 pub struct TgBot {
     bot: Bot,
+    /// Listener settings when updates come in through a webhook rather than long polling
+    webhook: Option<WebhookConfig>,
 }
 
 impl TgBot {
@@ -53,10 +149,14 @@ impl TgBot {
         dotenv().ok();
         let api_key = env::var("TG_BOT_API_KEY").expect("TG_BOT_API_KEY not set");
         let bot = Bot::new(api_key);
-        Self { bot }
+        let webhook = WebhookConfig::from_env()
+            .map(|config| config.unwrap_or_else(|err| panic!("Invalid webhook settings: {}", err)));
+        Self { bot, webhook }
     }
 
     pub async fn init(self) -> Result<(), TgError> {
+        tokio::spawn(run_scheduler(self.bot.clone()));
+
         let handler = dptree::entry()
             .branch(
                 Update::filter_message()
@@ -64,6 +164,22 @@ impl TgBot {
                     .endpoint(command_callback),
             )
             .branch(Update::filter_callback_query().endpoint(button_callback))
+            .branch(Update::filter_inline_query().endpoint(inline_query_handler))
+            .branch(Update::filter_chat_member().endpoint(chat_member_handler))
+            .branch(Update::filter_my_chat_member().endpoint(my_chat_member_handler))
+            .branch(Update::filter_poll_answer().endpoint(poll_answer_handler))
+            .branch(Update::filter_poll().endpoint(poll_handler))
+            // an admin's veto reason comes before comments, it may reply to the card
+            .branch(
+                Update::filter_message()
+                    .filter_map(find_pending_veto)
+                    .endpoint(receive_veto_reason_handler),
+            )
+            .branch(
+                Update::filter_message()
+                    .filter_map(find_replied_proposal_id)
+                    .endpoint(receive_comment_reply_handler),
+            )
             .branch(
                 Update::filter_message()
                     .enter_dialogue::<Message, InMemStorage<DialogueState>, DialogueState>()
@@ -72,40 +188,185 @@ impl TgBot {
                             .endpoint(start_title_dialogue_handler),
                     )
                     .branch(
-                        dptree::case![DialogueState::TitleReceived].endpoint(receive_title_handler),
-                    )
-                    .branch(
-                        dptree::case![DialogueState::DescriptionReceived]
-                            .endpoint(receive_description_handler),
-                    )
-                    .branch(
-                        dptree::case![DialogueState::StartingDateReceived]
-                            .endpoint(receive_starting_date_handler),
+                        dptree::case![DialogueState::FieldReceived { template, field }]
+                            .endpoint(receive_field_handler),
                     )
                     .branch(
-                        dptree::case![DialogueState::ExpirationDateReceived]
-                            .endpoint(receive_expiration_date_handler),
+                        dptree::case![DialogueState::CommentReceived { proposal_id }]
+                            .endpoint(receive_comment_handler),
                     ),
             );
 
-        Dispatcher::builder(self.bot, handler)
+        let mut dispatcher = Dispatcher::builder(self.bot.clone(), handler)
             .error_handler(LoggingErrorHandler::with_custom_text(
                 "An error has occurred in the dispatcher",
             ))
             .dependencies(dptree::deps![InMemStorage::<DialogueState>::new()])
             .enable_ctrlc_handler()
-            .build()
-            .dispatch()
-            .await;
+            .build();
+        match self.webhook {
+            Some(config) => {
+                let (listener, registered) = webhook_listener(&self.bot, config).await?;
+                dispatcher
+                    .dispatch_with_listener(
+                        listener,
+                        LoggingErrorHandler::with_custom_text("An error from the webhook listener"),
+                    )
+                    .await;
+                if registered {
+                    unregister_webhook(&self.bot).await;
+                }
+            }
+            None => dispatcher.dispatch().await,
+        }
         Ok(())
     }
 }
 
-async fn command_callback(bot: Bot, cmd: Command, msg: Message) -> Result<(), TgError> {
+async fn command_callback(
+    bot: Bot,
+    cmd: Command,
+    msg: Message,
+    storage: Arc<InMemStorage<DialogueState>>,
+) -> Result<(), TgError> {
     match cmd {
         Command::Help => {
             let _ = bot
                 .send_message(msg.chat.id, Command::descriptions().to_string())
+                .in_topic(topic(&msg))
+                .await?;
+        }
+        Command::Propose(text) => propose_command(&bot, &msg, &text, storage).await?,
+        Command::Proposal(id) => {
+            let Some(user) = msg.from() else {
+                return Ok(());
+            };
+            let Some(proposal_id) = parse_proposal_id(&id) else {
+                bot.send_message(msg.chat.id, "Usage: /proposal <number>, e.g. /proposal 12")
+                    .in_topic(topic(&msg))
+                    .await?;
+                return Ok(());
+            };
+            if msg.chat.is_private() {
+                send_linked_proposal(&bot, msg.chat.id, user.id, proposal_id).await?;
+                return Ok(());
+            }
+            let Some(proposal) = chat_proposal(&bot, &msg, proposal_id).await? else {
+                return Ok(());
+            };
+            let card = get_proposal_message(
+                &proposal,
+                GLOBAL_COMMENT_STORAGE.count(proposal.id),
+                chat_timezone(msg.chat.id),
+            );
+            bot.send_message(msg.chat.id, card)
+                .in_topic(topic(&msg))
+                .parse_mode(ParseMode::MarkdownV2)
+                .reply_markup(new_see_proporsal_keyboard(&proposal)?)
+                .await?;
+        }
+        Command::Results(id) => {
+            let Some(proposal_id) = parse_proposal_id(&id) else {
+                bot.send_message(msg.chat.id, "Usage: /results <number>, e.g. /results 12")
+                    .in_topic(topic(&msg))
+                    .await?;
+                return Ok(());
+            };
+            let Some(proposal) = chat_proposal(&bot, &msg, proposal_id).await? else {
+                return Ok(());
+            };
+            let reply = match proposal.status {
+                ProposalStatus::SeekingSponsors | ProposalStatus::Active => format!(
+                    "Proposal #{} is still {}, its results are shown once voting ends",
+                    proposal.id,
+                    proposal.status.to_string().to_lowercase()
+                ),
+                _ => get_detailed_results_message(&proposal, chat_timezone(msg.chat.id)),
+            };
+            bot.send_message(msg.chat.id, reply)
+                .in_topic(topic(&msg))
+                .await?;
+        }
+        Command::Search(query) => send_search_results(&bot, &msg, query.trim()).await?,
+        Command::MyVotes => {
+            let Some(user) = msg.from() else {
+                return Ok(());
+            };
+            let mut voted = GLOBAL_PROPOSAL_STORAGE
+                .all()
+                .into_iter()
+                .rev()
+                .filter(|proposal| msg.chat.is_private() || proposal.chat_id == msg.chat.id)
+                .filter(|proposal| proposal.voters.iter().any(|(voter, _)| *voter == user.id))
+                .collect::<Vec<_>>();
+            // members who left a chat no longer see its proposals
+            if msg.chat.is_private() {
+                voted = in_member_chats(&bot, user.id, voted).await;
+            }
+            let proposals = voted
+                .into_iter()
+                .filter_map(|proposal| {
+                    let (_, in_favour) = *proposal
+                        .voters
+                        .iter()
+                        .find(|(voter, _)| *voter == user.id)?;
+                    Some((proposal, in_favour))
+                })
+                .collect::<Vec<_>>();
+            let reply = match proposals.is_empty() {
+                true => {
+                    "You haven't voted on any proposal yet. Votes in anonymous polls aren't listed"
+                        .to_string()
+                }
+                false => get_proposal_list_message(
+                    &format!("Your votes, {}:", user_name(user)),
+                    &proposals
+                        .iter()
+                        .map(|(proposal, in_favour)| {
+                            let vote = match in_favour {
+                                true => "👍 for",
+                                false => "👎 against",
+                            };
+                            (proposal, format!("{}, {}", vote, proposal.status))
+                        })
+                        .collect::<Vec<_>>(),
+                ),
+            };
+            bot.send_message(msg.chat.id, reply)
+                .in_topic(topic(&msg))
+                .await?;
+        }
+        Command::MyProposals => {
+            let Some(user) = msg.from() else {
+                return Ok(());
+            };
+            let mut proposals = GLOBAL_PROPOSAL_STORAGE
+                .get(user.id)
+                .unwrap_or_default()
+                .into_iter()
+                .filter(|proposal| msg.chat.is_private() || proposal.chat_id == msg.chat.id)
+                .collect::<Vec<_>>();
+            proposals.sort_by_key(|proposal| Reverse(proposal.id));
+            let reply = match proposals.is_empty() {
+                true => "You haven't submitted any proposal yet".to_string(),
+                false => get_proposal_list_message(
+                    &format!("Proposals submitted by {}:", user_name(user)),
+                    &proposals
+                        .iter()
+                        .map(|proposal| {
+                            (
+                                proposal,
+                                format!(
+                                    "{} (👍 {} / 👎 {})",
+                                    proposal.status, proposal.vote, proposal.votes_against
+                                ),
+                            )
+                        })
+                        .collect::<Vec<_>>(),
+                ),
+            };
+            bot.send_message(msg.chat.id, reply)
+                .in_topic(topic(&msg))
                 .await?;
         }
         Command::Menu => {
@@ -115,32 +376,406 @@ async fn command_callback(bot: Bot, cmd: Command, msg: Message) -> Result<(), Tg
             // send the new message
             let message_sent = bot
                 .send_message(msg.chat.id, welcome_msg)
+                .in_topic(topic(&msg))
                 .parse_mode(ParseMode::MarkdownV2)
                 .reply_markup(keyboard)
                 .await?;
             let message_sent = Arc::new(message_sent);
 
             // Updates the GLOBAL_MAIN_MENU_STORAGE
-            let _user_name = message_sent
-                .clone()
-                .from()
-                .and_then(|user| user.username.as_ref())
-                .and_then(|user_name| {
-                    let message = TgMessage {
-                        chat_id: message_sent.chat.id,
-                        message_id: message_sent.id,
-                        message: message_sent.clone(),
-                    };
-                    GLOBAL_MAIN_MENU_STORAGE.insert(user_name.to_string(), message);
-                    Some(user_name)
-                });
+            if let Some(user) = msg.from() {
+                let message = TgMessage {
+                    chat_id: message_sent.chat.id,
+                    message_id: message_sent.id,
+                    message: message_sent.clone(),
+                };
+                GLOBAL_MAIN_MENU_STORAGE.insert(user_name(user), message);
+            }
 
-            // delete previous messages
-            let last_message_id = message_sent.id;
-            let _ =
+            // delete previous messages, in groups they belong to other members
+            if msg.chat.is_private() {
+                let last_message_id = message_sent.id;
                 delete_previous_messages(&bot, msg.chat.id.0, last_message_id.0 - 1, 20).await?;
+            }
+        }
+        Command::Sponsors(threshold) => {
+            if !is_sender_admin(&bot, &msg).await? {
+                bot.send_message(
+                    msg.chat.id,
+                    "Only chat admins can change the co-sponsor threshold",
+                )
+                .in_topic(topic(&msg))
+                .await?;
+                return Ok(());
+            }
+            let reply = match threshold.trim().parse::<usize>() {
+                Ok(sponsor_threshold) => {
+                    let mut settings = GLOBAL_CHAT_SETTINGS_STORAGE.get(msg.chat.id);
+                    settings.sponsor_threshold = sponsor_threshold;
+                    GLOBAL_CHAT_SETTINGS_STORAGE.insert(msg.chat.id, settings);
+                    format!(
+                        "New proposals now need {} co-sponsor(s) before voting opens",
+                        sponsor_threshold
+                    )
+                }
+                Err(_) => "Usage: /sponsors <number of co-sponsors>".to_string(),
+            };
+            bot.send_message(msg.chat.id, reply)
+                .in_topic(topic(&msg))
+                .await?;
+        }
+        Command::Categories(categories) => {
+            let categories = parse_tags(&categories);
+            let mut settings = GLOBAL_CHAT_SETTINGS_STORAGE.get(msg.chat.id);
+            let reply = match categories.is_empty() {
+                true if settings.categories.is_empty() => {
+                    "Usage: /categories budget, events, rules".to_string()
+                }
+                true => format!("Categories: {}", settings.categories.join(", ")),
+                false if !is_sender_admin(&bot, &msg).await? => {
+                    bot.send_message(msg.chat.id, "Only chat admins can change the categories")
+                        .in_topic(topic(&msg))
+                        .await?;
+                    return Ok(());
+                }
+                false => match categories
+                    .iter()
+                    .find(|category| category.len() > MAX_CATEGORY_LENGTH)
+                {
+                    Some(category) => {
+                        bot.send_message(
+                            msg.chat.id,
+                            format!(
+                                "\"{}\" is too long, categories can have up to {} characters",
+                                category, MAX_CATEGORY_LENGTH
+                            ),
+                        )
+                        .in_topic(topic(&msg))
+                        .await?;
+                        return Ok(());
+                    }
+                    None => format!("Categories set to: {}", categories.join(", ")),
+                },
+            };
+            if !categories.is_empty() {
+                settings.categories = categories;
+                GLOBAL_CHAT_SETTINGS_STORAGE.insert(msg.chat.id, settings);
+            }
+            bot.send_message(msg.chat.id, reply)
+                .in_topic(topic(&msg))
+                .await?;
+        }
+        Command::Template(definition) if definition.trim().is_empty() => {
+            let templates = GLOBAL_TEMPLATE_STORAGE.get(msg.chat.id);
+            let reply = match templates.is_empty() {
+                true => "This chat has no templates yet. Define one with:\n/template Budget\nAmount: number required\nLink: url\nKind: choice(hardware, software)".to_string(),
+                false => templates
+                    .iter()
+                    .map(ProposalTemplate::to_string)
+                    .collect::<Vec<_>>()
+                    .join("\n\n"),
+            };
+            bot.send_message(msg.chat.id, reply)
+                .in_topic(topic(&msg))
+                .await?;
+        }
+        Command::Template(definition) => {
+            if !is_sender_admin(&bot, &msg).await? {
+                bot.send_message(msg.chat.id, "Only chat admins can define templates")
+                    .in_topic(topic(&msg))
+                    .await?;
+                return Ok(());
+            }
+            let reply = match ProposalTemplate::parse(&definition) {
+                Ok(template) => {
+                    let reply = format!("Template saved:\n{}", template);
+                    GLOBAL_TEMPLATE_STORAGE.insert(msg.chat.id, template);
+                    reply
+                }
+                Err(reason) => reason,
+            };
+            bot.send_message(msg.chat.id, reply)
+                .in_topic(topic(&msg))
+                .await?;
+        }
+        Command::RemoveTemplate(name) => {
+            if !is_sender_admin(&bot, &msg).await? {
+                bot.send_message(msg.chat.id, "Only chat admins can remove templates")
+                    .in_topic(topic(&msg))
+                    .await?;
+                return Ok(());
+            }
+            let reply = match GLOBAL_TEMPLATE_STORAGE.remove(msg.chat.id, name.trim()) {
+                Some(template) => format!("Template {} removed", template.name),
+                None => format!("No template named \"{}\"", name.trim()),
+            };
+            bot.send_message(msg.chat.id, reply)
+                .in_topic(topic(&msg))
+                .await?;
+        }
+        Command::Timezone(timezone) if timezone.trim().is_empty() => {
+            let timezone = GLOBAL_CHAT_SETTINGS_STORAGE.get(msg.chat.id).timezone;
+            bot.send_message(
+                msg.chat.id,
+                format!("This chat's time zone is {}", timezone),
+            )
+            .in_topic(topic(&msg))
+            .await?;
+        }
+        Command::Timezone(timezone) => {
+            if !is_sender_admin(&bot, &msg).await? {
+                bot.send_message(msg.chat.id, "Only chat admins can change the time zone")
+                    .in_topic(topic(&msg))
+                    .await?;
+                return Ok(());
+            }
+            let reply = match parse_timezone(&timezone) {
+                Ok(timezone) => {
+                    let mut settings = GLOBAL_CHAT_SETTINGS_STORAGE.get(msg.chat.id);
+                    settings.timezone = timezone;
+                    GLOBAL_CHAT_SETTINGS_STORAGE.insert(msg.chat.id, settings);
+                    format!("Dates are now read and shown in {}", timezone)
+                }
+                Err(reason) => reason,
+            };
+            bot.send_message(msg.chat.id, reply)
+                .in_topic(topic(&msg))
+                .await?;
+        }
+        Command::Pin(setting) if setting.trim().is_empty() => {
+            let settings = GLOBAL_CHAT_SETTINGS_STORAGE.get(msg.chat.id);
+            bot.send_message(msg.chat.id, settings.pinning())
+                .in_topic(topic(&msg))
+                .await?;
+        }
+        Command::Pin(setting) => {
+            if !is_sender_admin(&bot, &msg).await? {
+                bot.send_message(msg.chat.id, "Only chat admins can change pinning")
+                    .in_topic(topic(&msg))
+                    .await?;
+                return Ok(());
+            }
+            let mut settings = GLOBAL_CHAT_SETTINGS_STORAGE.get(msg.chat.id);
+            let reply = match settings.set_pinning(&setting.to_lowercase()) {
+                Ok(()) => {
+                    let reply = settings.pinning();
+                    GLOBAL_CHAT_SETTINGS_STORAGE.insert(msg.chat.id, settings);
+                    reply
+                }
+                Err(reason) => reason,
+            };
+            bot.send_message(msg.chat.id, reply)
+                .in_topic(topic(&msg))
+                .await?;
+        }
+        Command::Channel(channel) if channel.trim().is_empty() => {
+            let reply = match GLOBAL_CHAT_SETTINGS_STORAGE.get(msg.chat.id).channel {
+                Some(channel) => match bot.get_chat(channel).await {
+                    Ok(chat) => format!(
+                        "Proposals and results are cross-posted to {}",
+                        chat.title().unwrap_or("the linked channel")
+                    ),
+                    Err(_) => "The linked channel can't be reached anymore".to_string(),
+                },
+                None => "This chat has no linked channel".to_string(),
+            };
+            bot.send_message(msg.chat.id, reply)
+                .in_topic(topic(&msg))
+                .await?;
+        }
+        Command::Channel(channel) => {
+            let Some(user) = msg.from() else {
+                return Ok(());
+            };
+            if !is_sender_admin(&bot, &msg).await? {
+                bot.send_message(msg.chat.id, "Only chat admins can link a channel")
+                    .in_topic(topic(&msg))
+                    .await?;
+                return Ok(());
+            }
+            let mut settings = GLOBAL_CHAT_SETTINGS_STORAGE.get(msg.chat.id);
+            let reply = match channel.trim() {
+                "off" => {
+                    settings.channel = None;
+                    GLOBAL_CHAT_SETTINGS_STORAGE.insert(msg.chat.id, settings);
+                    "Proposals are no longer cross-posted".to_string()
+                }
+                channel => match resolve_channel(&bot, channel, user.id).await {
+                    Ok(chat) => {
+                        settings.channel = Some(chat.id);
+                        GLOBAL_CHAT_SETTINGS_STORAGE.insert(msg.chat.id, settings);
+                        format!(
+                            "New proposals and results will be cross-posted to {}",
+                            chat.title().unwrap_or(channel)
+                        )
+                    }
+                    Err(reason) => reason,
+                },
+            };
+            bot.send_message(msg.chat.id, reply)
+                .in_topic(topic(&msg))
+                .await?;
+        }
+        Command::MemberAge(days) if days.trim().is_empty() => {
+            let settings = GLOBAL_CHAT_SETTINGS_STORAGE.get(msg.chat.id);
+            bot.send_message(msg.chat.id, settings.membership_age())
+                .in_topic(topic(&msg))
+                .await?;
+        }
+        Command::MemberAge(days) => {
+            if !is_sender_admin(&bot, &msg).await? {
+                bot.send_message(msg.chat.id, "Only chat admins can change who may vote")
+                    .in_topic(topic(&msg))
+                    .await?;
+                return Ok(());
+            }
+            // joins are only seen through chat_member updates, which Telegram sends to admins
+            let me = bot.get_me().await?;
+            let reply = match days.trim().parse::<u32>() {
+                Ok(days) if days > 0 && !is_chat_admin(&bot, msg.chat.id, me.id).await? => {
+                    "Make the bot an admin of the chat first, it can only tell when members joined as an admin".to_string()
+                }
+                Ok(days) => {
+                    let mut settings = GLOBAL_CHAT_SETTINGS_STORAGE.get(msg.chat.id);
+                    settings.min_membership_days = days;
+                    let reply = settings.membership_age();
+                    GLOBAL_CHAT_SETTINGS_STORAGE.insert(msg.chat.id, settings);
+                    reply
+                }
+                Err(_) => format!("\"{}\" is not a number of days", days.trim()),
+            };
+            bot.send_message(msg.chat.id, reply)
+                .in_topic(topic(&msg))
+                .await?;
+        }
+        Command::MyTimezone(timezone) => {
+            let Some(user) = msg.from() else {
+                return Ok(());
+            };
+            let mut settings = GLOBAL_USER_SETTINGS_STORAGE.get(user.id);
+            let reply = match timezone.trim() {
+                "" => match settings.timezone {
+                    Some(timezone) => format!("Your time zone is {}", timezone),
+                    None => "You follow the chat's time zone".to_string(),
+                },
+                "off" => {
+                    settings.timezone = None;
+                    GLOBAL_USER_SETTINGS_STORAGE.insert(user.id, settings);
+                    "You now follow the chat's time zone".to_string()
+                }
+                timezone => match parse_timezone(timezone) {
+                    Ok(timezone) => {
+                        settings.timezone = Some(timezone);
+                        GLOBAL_USER_SETTINGS_STORAGE.insert(user.id, settings);
+                        format!(
+                            "Dates in your private chat with the bot are now read and shown in {}",
+                            timezone
+                        )
+                    }
+                    Err(reason) => reason,
+                },
+            };
+            bot.send_message(msg.chat.id, reply)
+                .in_topic(topic(&msg))
+                .await?;
+        }
+        Command::Calendar => {
+            let now = Utc::now();
+            let proposals = GLOBAL_PROPOSAL_STORAGE
+                .all()
+                .into_iter()
+                .filter(|proposal| {
+                    proposal.chat_id == msg.chat.id
+                        && matches!(
+                            proposal.status,
+                            ProposalStatus::SeekingSponsors | ProposalStatus::Active
+                        )
+                        && proposal.expiration_date.is_some_and(|date| date > now)
+                })
+                .collect::<Vec<_>>();
+            if proposals.is_empty() {
+                bot.send_message(msg.chat.id, "There are no upcoming voting windows")
+                    .in_topic(topic(&msg))
+                    .await?;
+                return Ok(());
+            }
+            bot.send_document(
+                msg.chat.id,
+                InputFile::memory(proposals_calendar(&proposals, now)).file_name("proposals.ics"),
+            )
+            .in_topic(topic(&msg))
+            .await?;
+        }
+        Command::Recurring(definition) if definition.trim().is_empty() => {
+            let timezone = chat_timezone(msg.chat.id);
+            let recurrings = GLOBAL_RECURRING_STORAGE.get(msg.chat.id);
+            let reply = match recurrings.is_empty() {
+                true => "This chat has no recurring proposals yet. Define one with:\n/recurring Monthly budget sign-off\nSchedule: 0 9 1 * *\nVoting: 7 days\nDescription: Approve next month's budget".to_string(),
+                false => recurrings
+                    .iter()
+                    .map(|recurring| recurring.summary(timezone))
+                    .collect::<Vec<_>>()
+                    .join("\n\n"),
+            };
+            bot.send_message(msg.chat.id, reply)
+                .in_topic(topic(&msg))
+                .await?;
+        }
+        Command::Recurring(definition) => {
+            if !is_sender_admin(&bot, &msg).await? {
+                bot.send_message(msg.chat.id, "Only chat admins can schedule proposals")
+                    .in_topic(topic(&msg))
+                    .await?;
+                return Ok(());
+            }
+            let timezone = chat_timezone(msg.chat.id);
+            let reply =
+                match RecurringProposal::parse(&definition, msg.chat.id, Utc::now(), timezone) {
+                    Ok(recurring) => {
+                        let reply =
+                            format!("Recurring proposal saved:\n{}", recurring.summary(timezone));
+                        GLOBAL_RECURRING_STORAGE.insert(msg.chat.id, recurring);
+                        reply
+                    }
+                    Err(reason) => reason,
+                };
+            bot.send_message(msg.chat.id, reply)
+                .in_topic(topic(&msg))
+                .await?;
+        }
+        Command::RemoveRecurring(title) => {
+            if !is_sender_admin(&bot, &msg).await? {
+                bot.send_message(msg.chat.id, "Only chat admins can stop recurring proposals")
+                    .in_topic(topic(&msg))
+                    .await?;
+                return Ok(());
+            }
+            let reply = match GLOBAL_RECURRING_STORAGE.remove(msg.chat.id, title.trim()) {
+                Some(recurring) => format!("Recurring proposal {} stopped", recurring.title),
+                None => format!("No recurring proposal titled \"{}\"", title.trim()),
+            };
+            bot.send_message(msg.chat.id, reply)
+                .in_topic(topic(&msg))
+                .await?;
         }
-        Command::Start => {
+        Command::Start(payload)
+            if msg.chat.is_private() && payload.starts_with(PROPOSAL_LINK_PREFIX) =>
+        {
+            match payload[PROPOSAL_LINK_PREFIX.len()..].parse::<u64>() {
+                Ok(proposal_id) => {
+                    let user_id = msg
+                        .from()
+                        .map_or(UserId(msg.chat.id.0 as u64), |user| user.id);
+                    send_linked_proposal(&bot, msg.chat.id, user_id, proposal_id).await?
+                }
+                Err(_) => {
+                    bot.send_message(msg.chat.id, "This link doesn't point to a proposal")
+                        .in_topic(topic(&msg))
+                        .await?;
+                }
+            }
+        }
+        Command::Start(_) => {
             sleep(Duration::from_secs(1)).await;
             let keyboard = menu_keyboard();
             let menu_msg = get_welcome_message();
@@ -148,6 +783,7 @@ async fn command_callback(bot: Bot, cmd: Command, msg: Message) -> Result<(), Tg
             // send the welcome message
             let _message_sent = bot
                 .send_message(msg.chat.id, menu_msg)
+                .in_topic(topic(&msg))
                 .parse_mode(ParseMode::MarkdownV2)
                 .reply_markup(keyboard)
                 .await?;
@@ -156,6 +792,41 @@ async fn command_callback(bot: Bot, cmd: Command, msg: Message) -> Result<(), Tg
     Ok(())
 }
 
+/// Reads a proposal number such as "12" or "#12"
+fn parse_proposal_id(text: &str) -> Option<u64> {
+    text.trim().trim_start_matches('#').parse().ok()
+}
+
+/// The proposal a command asks for, if the one asking may see it: groups only see their own
+/// proposals, private chats those of the chats the member is in. Tells them otherwise
+async fn chat_proposal(
+    bot: &Bot,
+    msg: &Message,
+    proposal_id: u64,
+) -> Result<Option<Proposal>, TgError> {
+    let visible = match (GLOBAL_PROPOSAL_STORAGE.get_by_id(proposal_id), msg.from()) {
+        (Some(proposal), _) if proposal.chat_id == msg.chat.id => Some(proposal),
+        (Some(proposal), Some(user))
+            if msg.chat.is_private() && is_chat_member(bot, proposal.chat_id, user.id).await =>
+        {
+            Some(proposal)
+        }
+        _ => None,
+    };
+    if visible.is_none() {
+        bot.send_message(
+            msg.chat.id,
+            format!(
+                "Proposal #{} doesn't exist or belongs to another chat",
+                proposal_id
+            ),
+        )
+        .in_topic(topic(msg))
+        .await?;
+    }
+    Ok(visible)
+}
+
 async fn button_callback(
     bot: Bot,
     q: CallbackQuery,
@@ -166,57 +837,97 @@ async fn button_callback(
             CREATE_A_PROPOSAL => handle_new_proposal_callback(&bot, &q).await?,
             SEE_PROPOSALS => handle_see_proposals_callback(&bot, &q).await?,
             MAIN_MENU => handle_menu_callback(&bot, &q).await?,
+            // cards shared through inline mode only carry vote buttons
+            _ if q.inline_message_id.is_some() => match SeeProposalsKeyboard::new(action) {
+                SeeProposalsKeyboard::ThumbUp(proposal_id) => {
+                    handle_vote_callback(&bot, &q, proposal_id, true).await?
+                }
+                SeeProposalsKeyboard::ThumbDown(proposal_id) => {
+                    handle_vote_callback(&bot, &q, proposal_id, false).await?
+                }
+                _ => {
+                    bot.answer_callback_query(&q.id).await?;
+                }
+            },
             _ => match match_sub_menu(&q) {
                 Some(SubMenuType::CreateNewProposal) => {
                     match CreateNewProposalKeyboard::new(action) {
-                        CreateNewProposalKeyboard::Title(_) => {
-                            handle_proposal_fields_callback(
-                                &bot,
-                                DialogueState::TitleReceived,
-                                &q,
-                                storage,
-                                CreateNewProposalKeyboard::Title(""),
-                            )
-                            .await?
+                        CreateNewProposalKeyboard::Template(template) => {
+                            handle_template_callback(&bot, &q, &template).await?
                         }
-                        CreateNewProposalKeyboard::Description(_) => {
-                            handle_proposal_fields_callback(
-                                &bot,
-                                DialogueState::DescriptionReceived,
-                                &q,
-                                storage,
-                                CreateNewProposalKeyboard::Description(""),
-                            )
-                            .await?
+                        CreateNewProposalKeyboard::Field(template, field) => {
+                            handle_proposal_fields_callback(&bot, &q, storage, template, field)
+                                .await?
                         }
-                        CreateNewProposalKeyboard::StartingDate(_) => {
-                            handle_proposal_fields_callback(
-                                &bot,
-                                DialogueState::StartingDateReceived,
-                                &q,
-                                storage,
-                                CreateNewProposalKeyboard::StartingDate(""),
-                            )
-                            .await?
+                        CreateNewProposalKeyboard::SubmitProposal(template) => {
+                            handle_submit_proposal_callback(&bot, &q, template).await?
                         }
-                        CreateNewProposalKeyboard::ExpirationDate(_) => {
-                            handle_proposal_fields_callback(
-                                &bot,
-                                DialogueState::ExpirationDateReceived,
-                                &q,
-                                storage,
-                                CreateNewProposalKeyboard::ExpirationDate(""),
-                            )
-                            .await?
+                        CreateNewProposalKeyboard::Close => {
+                            handle_close_draft_callback(&bot, &q).await?
+                        }
+                        CreateNewProposalKeyboard::MainMenu => {
+                            handle_menu_callback(&bot, &q).await?
                         }
-                        _ => handle_submit_proposal_callback(&bot, &q).await?,
                     }
                 }
                 Some(SubMenuType::SeeProposals) => match SeeProposalsKeyboard::new(action) {
-                    SeeProposalsKeyboard::ThumbUp => {
-                        handle_thumb_up_callback(&bot, &q, SeeProposalsKeyboard::ThumbUp).await?
+                    SeeProposalsKeyboard::ThumbUp(proposal_id) => {
+                        handle_vote_callback(&bot, &q, proposal_id, true).await?
+                    }
+                    SeeProposalsKeyboard::ThumbDown(proposal_id) => {
+                        handle_vote_callback(&bot, &q, proposal_id, false).await?
+                    }
+                    SeeProposalsKeyboard::CloseVoting(proposal_id) => {
+                        handle_close_voting_callback(&bot, &q, proposal_id).await?
                     }
+                    SeeProposalsKeyboard::Veto(proposal_id) => {
+                        handle_veto_callback(&bot, &q, proposal_id).await?
+                    }
+                    SeeProposalsKeyboard::CancelVeto(proposal_id) => {
+                        handle_cancel_veto_callback(&bot, &q, proposal_id).await?
+                    }
+                    SeeProposalsKeyboard::Discuss(proposal_id) => {
+                        handle_discuss_callback(&bot, &q, proposal_id, None).await?
+                    }
+                    SeeProposalsKeyboard::CommentsPage(proposal_id, page) => {
+                        handle_discuss_callback(&bot, &q, proposal_id, Some(page)).await?
+                    }
+                    SeeProposalsKeyboard::Comment(proposal_id) => {
+                        handle_comment_callback(&bot, &q, proposal_id, storage).await?
+                    }
+                    SeeProposalsKeyboard::Sponsor(proposal_id) => {
+                        handle_sponsor_callback(&bot, &q, proposal_id).await?
+                    }
+                    SeeProposalsKeyboard::Filter(category) => {
+                        handle_filter_proposals_callback(&bot, &q, category).await?
+                    }
+                    SeeProposalsKeyboard::Browse(page, sort, category) => {
+                        handle_browse_callback(&bot, &q, page, sort, category).await?
+                    }
+                    SeeProposalsKeyboard::Open(proposal_id) => {
+                        handle_open_proposal_callback(&bot, &q, proposal_id).await?
+                    }
+                    SeeProposalsKeyboard::Sort(sort, shown, category) => {
+                        handle_sort_callback(&bot, &q, sort, shown, category).await?
+                    }
+                    SeeProposalsKeyboard::SearchPage(page) => {
+                        handle_search_page_callback(&bot, &q, page).await?
+                    }
+                    SeeProposalsKeyboard::PageIndicator => {
+                        bot.answer_callback_query(&q.id).await?;
+                    }
+                    SeeProposalsKeyboard::AddToCalendar(proposal_id) => {
+                        handle_add_to_calendar_callback(&bot, &q, proposal_id).await?
+                    }
+                    SeeProposalsKeyboard::Share(proposal_id) => {
+                        handle_share_callback(&bot, &q, proposal_id).await?
+                    }
+                    SeeProposalsKeyboard::Unknown => {}
                 },
+                Some(SubMenuType::Calendar) => {
+                    handle_calendar_callback(&bot, &q, storage, CalendarKeyboard::new(action))
+                        .await?
+                }
                 _ => {}
             },
         }
@@ -8,5 +8,60 @@ pub const TITLE: &str = "Title";
 pub const DESCRIPTION: &str = "Description";
 pub const STARTING_DATE: &str = "Starting Date";
 pub const EXPIRATION_DATE: &str = "Expiration Date";
+pub const TAGS: &str = "Tags";
+pub const BALLOT: &str = "Ballot";
+pub const BALLOT_BUTTONS: &str = "buttons";
+pub const BALLOT_POLL: &str = "poll";
+pub const BALLOT_ANONYMOUS_POLL: &str = "anonymous poll";
+pub const POLL_FOR: &str = "👍 For";
+pub const POLL_AGAINST: &str = "👎 Against";
 pub const THUMB_UP: &str = "👍";
 pub const THUMB_DOWN: &str = "👎";
+pub const DISCUSS: &str = "💬 Discuss";
+pub const COMMENT: &str = "✍️ Comment";
+pub const PREVIOUS_PAGE: &str = "◀";
+pub const NEXT_PAGE: &str = "▶";
+pub const COMMENTS_PER_PAGE: usize = 5;
+pub const BROWSE: &str = "📚";
+pub const OPEN: &str = "📖 Open";
+pub const PAGE_INDICATOR: &str = "📄";
+pub const SEARCH: &str = "🔍";
+pub const SORT: &str = "↕";
+pub const SORT_NEWEST: &str = "🆕 Newest";
+pub const SORT_MOST_VOTES: &str = "🗳 Most votes";
+pub const SORT_ENDING_SOONEST: &str = "⏳ Ending soonest";
+pub const SORT_MOST_DISCUSSED: &str = "💬 Most discussed";
+pub const SELECTED: &str = "✅";
+pub const PROPOSALS_PER_PAGE: usize = 5;
+pub const SPONSOR: &str = "🤝 Sponsor";
+pub const DEFAULT_SPONSOR_THRESHOLD: usize = 1;
+pub const DEFAULT_PIN_RESULTS_HOURS: u32 = 24;
+pub const ALL_CATEGORIES: &str = "All";
+/// Longest category name, they are sent in callback data which Telegram caps at 64 bytes
+pub const MAX_CATEGORY_LENGTH: usize = 32;
+pub const FILTER: &str = "🏷";
+pub const TEMPLATE: &str = "📋";
+/// Longest template name in bytes, the template picker sends it in callback data
+pub const MAX_TEMPLATE_NAME_LENGTH: usize = 32;
+pub const FIELD: &str = "✏️";
+pub const CLOSE_VOTING: &str = "🔒 Close voting";
+pub const VETO: &str = "⛔ Veto";
+pub const CANCEL_VETO: &str = "✖ Cancel veto";
+pub const CALENDAR_MONTH: &str = "📅 Month";
+pub const CALENDAR_DAY: &str = "📅 Day";
+pub const CALENDAR_HOUR: &str = "📅 Hour";
+pub const CALENDAR_MINUTE: &str = "📅 Minute";
+pub const CALENDAR_IGNORE: &str = "📅";
+pub const CALENDAR_CANCEL: &str = "✖ Cancel";
+pub const ADD_TO_CALENDAR: &str = "📅 Add to calendar";
+pub const SHARE: &str = "🔗 Share";
+pub const VOTE_IN_CHAT: &str = "🗳 Open the vote";
+/// One of the colors Telegram allows for forum topic icons
+pub const TOPIC_ICON_COLOR: u32 = 0x6FB9F0;
+/// Prefix of the start payload of links to a proposal, e.g. t.me/zuzarule_bot?start=p_12
+pub const PROPOSAL_LINK_PREFIX: &str = "p_";
+pub const SCHEDULER_INTERVAL: std::time::Duration = std::time::Duration::from_secs(30);
+/// How long cached chat admins are trusted when no chat member update arrives
+pub const ADMIN_CACHE_MINUTES: i64 = 10;
+/// Most proposals /myvotes and /myproposals list at once
+pub const MAX_LISTED_PROPOSALS: usize = 30;
@@ -0,0 +1,270 @@
+use chrono::{DateTime, Datelike, Duration, NaiveDateTime, TimeZone, Timelike, Utc};
+use chrono_tz::Tz;
+use std::fmt;
+
+const MONTH_NAMES: [&str; 12] = [
+    "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+];
+const WEEKDAY_NAMES: [&str; 7] = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+
+/// How far ahead to look for the next run before giving up, e.g. on "0 0 30 2 *"
+const MAX_YEARS_AHEAD: i64 = 5;
+
+/// A cron-like schedule: "minute hour day-of-month month day-of-week", e.g. "0 9 1 * *"
+/// for 09:00 on the first of every month. Fields take `*`, numbers, names (jan, mon),
+/// lists (1,15), ranges (mon-fri) and steps (*/15). @hourly, @daily, @weekly, @monthly
+/// and @yearly are shortcuts
+#[derive(Debug, Clone)]
+pub(crate) struct Schedule {
+    expression: String,
+    minutes: Vec<bool>,
+    hours: Vec<bool>,
+    days: Vec<bool>,
+    months: Vec<bool>,
+    weekdays: Vec<bool>,
+    /// As in cron, when both day fields are restricted either one matching is enough
+    any_day: bool,
+    any_weekday: bool,
+}
+
+/// Parses one field into a lookup table indexed by value
+fn parse_field(field: &str, min: u32, max: u32, names: &[&str]) -> Result<Vec<bool>, String> {
+    let value = |text: &str| -> Result<u32, String> {
+        let name = names
+            .iter()
+            .position(|name| name.eq_ignore_ascii_case(text))
+            .map(|index| index as u32 + min);
+        match name.or_else(|| text.parse::<u32>().ok()) {
+            Some(value) if (min..=max).contains(&value) => Ok(value),
+            _ => Err(format!(
+                "\"{}\" has to be between {} and {}",
+                text, min, max
+            )),
+        }
+    };
+
+    let mut table = vec![false; max as usize + 1];
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (
+                range,
+                step.parse::<u32>()
+                    .ok()
+                    .filter(|step| *step > 0)
+                    .ok_or_else(|| format!("Invalid step in \"{}\"", part))?,
+            ),
+            None => (part, 1),
+        };
+        let (start, end) = match range {
+            "*" => (min, max),
+            range => match range.split_once('-') {
+                Some((start, end)) => (value(start)?, value(end)?),
+                // "5/15" runs from 5 to the end of the range
+                None if step > 1 => (value(range)?, max),
+                None => (value(range)?, value(range)?),
+            },
+        };
+        if start > end {
+            return Err(format!("\"{}\" is an empty range", part));
+        }
+        for value in (start..=end).step_by(step as usize) {
+            table[value as usize] = true;
+        }
+    }
+    Ok(table)
+}
+
+impl Schedule {
+    pub(crate) fn parse(expression: &str) -> Result<Self, String> {
+        let expression = expression.trim();
+        let expanded = match expression.to_lowercase().as_str() {
+            "@hourly" => "0 * * * *",
+            "@daily" => "0 0 * * *",
+            "@weekly" => "0 0 * * 0",
+            "@monthly" => "0 0 1 * *",
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            _ => expression,
+        };
+        let fields = expanded.split_whitespace().collect::<Vec<_>>();
+        let [minute, hour, day, month, weekday] = fields[..] else {
+            return Err(format!(
+                "\"{}\" should have 5 fields: minute hour day-of-month month day-of-week",
+                expression
+            ));
+        };
+
+        let mut weekdays = parse_field(weekday, 0, 7, &WEEKDAY_NAMES)?;
+        // both 0 and 7 are Sunday
+        weekdays[0] |= weekdays[7];
+        weekdays.truncate(7);
+
+        Ok(Schedule {
+            expression: expression.to_string(),
+            minutes: parse_field(minute, 0, 59, &[])?,
+            hours: parse_field(hour, 0, 23, &[])?,
+            days: parse_field(day, 1, 31, &[])?,
+            months: parse_field(month, 1, 12, &MONTH_NAMES)?,
+            weekdays,
+            any_day: day.starts_with('*'),
+            any_weekday: weekday.starts_with('*'),
+        })
+    }
+
+    fn matches_day(&self, date: &NaiveDateTime) -> bool {
+        let day = self.days[date.day() as usize];
+        let weekday = self.weekdays[date.weekday().num_days_from_sunday() as usize];
+        match (self.any_day, self.any_weekday) {
+            (true, true) => true,
+            (true, false) => weekday,
+            (false, true) => day,
+            (false, false) => day || weekday,
+        }
+    }
+
+    /// The first time after `after` the schedule fires, read in `timezone`
+    pub(crate) fn next_after(&self, after: DateTime<Utc>, timezone: Tz) -> Option<DateTime<Utc>> {
+        let local = after.with_timezone(&timezone).naive_local();
+        let mut date = local.with_second(0)?.with_nanosecond(0)? + Duration::minutes(1);
+        let limit = local + Duration::days(366 * MAX_YEARS_AHEAD);
+
+        while date < limit {
+            if !self.months[date.month() as usize] {
+                let (year, month) = match date.month() {
+                    12 => (date.year() + 1, 1),
+                    month => (date.year(), month + 1),
+                };
+                date = chrono::NaiveDate::from_ymd_opt(year, month, 1)?.and_hms_opt(0, 0, 0)?;
+            } else if !self.matches_day(&date) {
+                date = (date.date() + Duration::days(1)).and_hms_opt(0, 0, 0)?;
+            } else if !self.hours[date.hour() as usize] {
+                date = date.with_minute(0)? + Duration::hours(1);
+            } else if !self.minutes[date.minute() as usize] {
+                date += Duration::minutes(1);
+            } else {
+                // times skipped by a daylight saving change don't fire
+                match timezone.from_local_datetime(&date).earliest() {
+                    Some(next) => return Some(next.with_timezone(&Utc)),
+                    None => date += Duration::minutes(1),
+                }
+            }
+        }
+        None
+    }
+}
+
+impl fmt::Display for Schedule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.expression)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utc(year: i32, month: u32, day: u32, hour: u32, minute: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(year, month, day, hour, minute, 0)
+            .unwrap()
+    }
+
+    fn next(expression: &str, after: DateTime<Utc>, timezone: Tz) -> Option<DateTime<Utc>> {
+        Schedule::parse(expression)
+            .unwrap()
+            .next_after(after, timezone)
+    }
+
+    #[test]
+    fn rejects_invalid_expressions() {
+        assert!(Schedule::parse("0 9 * *").is_err());
+        assert!(Schedule::parse("60 * * * *").is_err());
+        assert!(Schedule::parse("0 24 * * *").is_err());
+        assert!(Schedule::parse("0 0 0 * *").is_err());
+        assert!(Schedule::parse("*/0 * * * *").is_err());
+        assert!(Schedule::parse("0 0 * * fri-mon").is_err());
+        assert!(Schedule::parse("0 0 * foo *").is_err());
+        assert!(Schedule::parse("@fortnightly").is_err());
+    }
+
+    #[test]
+    fn keeps_the_expression() {
+        let schedule = Schedule::parse(" @Monthly ").unwrap();
+        assert_eq!(schedule.to_string(), "@Monthly");
+    }
+
+    #[test]
+    fn finds_the_next_run() {
+        let now = utc(2024, 5, 1, 12, 7);
+        assert_eq!(
+            next("*/15 * * * *", now, Tz::UTC),
+            Some(utc(2024, 5, 1, 12, 15))
+        );
+        assert_eq!(next("@hourly", now, Tz::UTC), Some(utc(2024, 5, 1, 13, 0)));
+        assert_eq!(next("@daily", now, Tz::UTC), Some(utc(2024, 5, 2, 0, 0)));
+        assert_eq!(next("0 9 1 * *", now, Tz::UTC), Some(utc(2024, 6, 1, 9, 0)));
+        assert_eq!(
+            next("0 9 * * mon-fri", now, Tz::UTC),
+            Some(utc(2024, 5, 2, 9, 0))
+        );
+        assert_eq!(
+            next("0 0 1 jan *", now, Tz::UTC),
+            Some(utc(2025, 1, 1, 0, 0))
+        );
+        // both 0 and 7 are Sunday
+        assert_eq!(next("0 0 * * 7", now, Tz::UTC), Some(utc(2024, 5, 5, 0, 0)));
+        assert_eq!(next("0 0 * * 0", now, Tz::UTC), Some(utc(2024, 5, 5, 0, 0)));
+        // a run exactly at `after` is not the next one
+        assert_eq!(
+            next("0 12 * * *", utc(2024, 5, 1, 12, 0), Tz::UTC),
+            Some(utc(2024, 5, 2, 12, 0))
+        );
+    }
+
+    #[test]
+    fn skips_months_without_the_day() {
+        let now = utc(2024, 4, 1, 0, 0);
+        assert_eq!(
+            next("0 0 31 * *", now, Tz::UTC),
+            Some(utc(2024, 5, 31, 0, 0))
+        );
+        assert_eq!(
+            next("0 0 29 2 *", now, Tz::UTC),
+            Some(utc(2028, 2, 29, 0, 0))
+        );
+        assert_eq!(next("0 0 30 2 *", now, Tz::UTC), None);
+    }
+
+    #[test]
+    fn matches_either_day_field_when_both_are_set() {
+        // Friday the 3rd comes before the 13th
+        assert_eq!(
+            next("0 0 13 * fri", utc(2024, 5, 1, 0, 0), Tz::UTC),
+            Some(utc(2024, 5, 3, 0, 0))
+        );
+    }
+
+    #[test]
+    fn runs_in_the_time_zone() {
+        // 08:00 in New York
+        assert_eq!(
+            next("0 9 * * *", utc(2024, 5, 1, 12, 0), Tz::America__New_York),
+            Some(utc(2024, 5, 1, 13, 0))
+        );
+    }
+
+    #[test]
+    fn handles_daylight_saving_changes() {
+        let berlin = Tz::Europe__Berlin;
+        // 02:30 doesn't exist on 2024-03-31, the clocks jump from 02:00 to 03:00
+        assert_eq!(
+            next("30 2 * * *", utc(2024, 3, 30, 12, 0), berlin),
+            Some(utc(2024, 4, 1, 0, 30))
+        );
+        // 02:30 happens twice on 2024-10-27, the schedule fires on the first one only
+        let first = next("30 2 * * *", utc(2024, 10, 26, 12, 0), berlin);
+        assert_eq!(first, Some(utc(2024, 10, 27, 0, 30)));
+        assert_eq!(
+            next("30 2 * * *", first.unwrap(), berlin),
+            Some(utc(2024, 10, 28, 1, 30))
+        );
+    }
+}
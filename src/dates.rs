@@ -0,0 +1,356 @@
+use crate::storage::{
+    TgChatSettingsStorage, TgUserSettingsStorage, GLOBAL_CHAT_SETTINGS_STORAGE,
+    GLOBAL_USER_SETTINGS_STORAGE,
+};
+use chrono::{
+    DateTime, Datelike, Duration, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Utc, Weekday,
+};
+use chrono_tz::Tz;
+use lazy_static::lazy_static;
+use regex::Regex;
+use teloxide::types::{ChatId, UserId};
+
+lazy_static! {
+    /// "2024-05-01t18:00" is the ISO form without a zone, once lowercased
+    static ref ISO_DATE: Regex = Regex::new(r"^(\d{4}-\d{2}-\d{2})t").unwrap();
+    static ref OFFSET: Regex = Regex::new(r"^in (\d+) (minute|hour|day|week)s?$").unwrap();
+    static ref TIME_OF_DAY: Regex =
+        Regex::new(r"^(.+?)(?:,?\s+(?:at\s+)?(\d{1,2})(?::(\d{2}))?\s*(am|pm)?)?$").unwrap();
+}
+
+/// How dates are shown on cards and written back into proposal drafts, followed by the zone name
+pub(crate) const DATE_FORMAT: &str = "%Y-%m-%d %H:%M";
+
+const DATE_FORMATS: [&str; 10] = [
+    "%Y-%m-%d",
+    "%Y/%m/%d",
+    "%d/%m/%Y",
+    "%d.%m.%Y",
+    "%d-%m-%Y",
+    "%d %B %Y",
+    "%d %b %Y",
+    "%B %d %Y",
+    "%b %d %Y",
+    "%B %d, %Y",
+];
+
+/// Formats without a year, the next occurrence of the day is used
+const YEARLESS_DATE_FORMATS: [&str; 6] = ["%d/%m", "%d.%m", "%d %B", "%d %b", "%B %d", "%b %d"];
+
+const WEEKDAYS: [(&str, Weekday); 7] = [
+    ("monday", Weekday::Mon),
+    ("tuesday", Weekday::Tue),
+    ("wednesday", Weekday::Wed),
+    ("thursday", Weekday::Thu),
+    ("friday", Weekday::Fri),
+    ("saturday", Weekday::Sat),
+    ("sunday", Weekday::Sun),
+];
+
+pub(crate) fn format_date(date: &DateTime<Utc>, timezone: Tz) -> String {
+    format!(
+        "{} {}",
+        date.with_timezone(&timezone).format(DATE_FORMAT),
+        timezone.name()
+    )
+}
+
+/// Zone dates are read and shown in for a chat: the user's own zone in their private chat
+/// with the bot, the chat's `/timezone` otherwise
+pub(crate) fn chat_timezone(chat_id: ChatId) -> Tz {
+    let timezone = GLOBAL_CHAT_SETTINGS_STORAGE.get(chat_id).timezone;
+    match chat_id.is_user() {
+        true => GLOBAL_USER_SETTINGS_STORAGE
+            .get(UserId(chat_id.0 as u64))
+            .timezone
+            .unwrap_or(timezone),
+        false => timezone,
+    }
+}
+
+/// Parses a time zone name such as "Europe/Berlin" or "utc"
+pub(crate) fn parse_timezone(text: &str) -> Result<Tz, String> {
+    Tz::from_str_insensitive(text.trim()).map_err(|_| {
+        format!(
+            "Unknown time zone \"{}\". Use a name like UTC, Europe/Berlin or America/New_York",
+            text.trim()
+        )
+    })
+}
+
+/// Parses a date typed by a user: ISO 8601 ("2024-05-01T18:00:00Z", "2024-05-01 18:00"),
+/// day/month forms ("01/05/2024", "1 May 18:00") and relative ones ("tomorrow 18:00",
+/// "in 3 days", "next friday"). Relative dates are resolved against `now`. Dates are read in
+/// `timezone` unless they end with a zone of their own, e.g. "2024-05-01 18:00 Europe/Berlin"
+pub(crate) fn parse_date(
+    text: &str,
+    now: DateTime<Utc>,
+    timezone: Tz,
+) -> Result<DateTime<Utc>, String> {
+    let text = text.trim();
+    if let Ok(date) = DateTime::parse_from_rfc3339(text) {
+        return Ok(date.with_timezone(&Utc));
+    }
+
+    let (text, timezone) = match text
+        .rsplit_once(char::is_whitespace)
+        .and_then(|(date, zone)| Some((date, Tz::from_str_insensitive(zone).ok()?)))
+    {
+        Some((date, zone)) => (date.trim(), zone),
+        None => (text, timezone),
+    };
+    let text = text.to_lowercase();
+    let text = ISO_DATE.replace(&text, "$1 ");
+
+    let now = now.with_timezone(&timezone);
+    parse_relative_date(&text, now)
+        .or_else(|| parse_absolute_date(&text, now))
+        .ok_or_else(|| {
+            format!(
+                "Could not read \"{}\" as a date. Try 2024-05-01 18:00, 01/05/2024, tomorrow 18:00 or in 3 days",
+                text
+            )
+        })
+}
+
+/// Checks that the voting window makes sense: it has to end after it starts, and not in the past
+pub(crate) fn check_voting_window(
+    starting_date: Option<DateTime<Utc>>,
+    expiration_date: Option<DateTime<Utc>>,
+    now: DateTime<Utc>,
+    timezone: Tz,
+) -> Result<(), String> {
+    match (starting_date, expiration_date) {
+        (_, Some(expiration_date)) if expiration_date <= now => Err(format!(
+            "The Expiration Date {} is in the past",
+            format_date(&expiration_date, timezone)
+        )),
+        (Some(starting_date), Some(expiration_date)) if expiration_date <= starting_date => {
+            Err(format!(
+                "The Expiration Date {} has to be after the Starting Date {}",
+                format_date(&expiration_date, timezone),
+                format_date(&starting_date, timezone)
+            ))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Resolves a local date and time, `None` when it falls in a daylight saving gap
+/// `amount` minutes, hours, days or weeks, `None` when that is too long for a date
+pub(crate) fn unit_duration(amount: u64, unit: &str) -> Option<Duration> {
+    let seconds = match unit {
+        "minute" => 60,
+        "hour" => 60 * 60,
+        "day" => 24 * 60 * 60,
+        _ => 7 * 24 * 60 * 60,
+    };
+    let seconds = amount.checked_mul(seconds)?;
+    Duration::from_std(std::time::Duration::from_secs(seconds)).ok()
+}
+
+fn localize(date: NaiveDateTime, timezone: Tz) -> Option<DateTime<Utc>> {
+    timezone
+        .from_local_datetime(&date)
+        .earliest()
+        .map(|date| date.with_timezone(&Utc))
+}
+
+fn parse_relative_date(text: &str, now: DateTime<Tz>) -> Option<DateTime<Utc>> {
+    if let Some(caps) = OFFSET.captures(text) {
+        let amount = caps[1].parse::<u64>().ok()?;
+        let date = now.checked_add_signed(unit_duration(amount, &caps[2])?)?;
+        return Some(date.with_timezone(&Utc));
+    }
+
+    let (day, time) = split_time(text)?;
+    let today = now.date_naive();
+    let date = match day {
+        "today" => today,
+        "tomorrow" => today + Duration::days(1),
+        day => {
+            let weekday = day.strip_prefix("next ").unwrap_or(day);
+            let (_, weekday) = WEEKDAYS.iter().find(|(name, _)| *name == weekday)?;
+            // the coming one, a week ahead when it is today
+            let days_ahead =
+                (weekday.num_days_from_monday() + 7 - today.weekday().num_days_from_monday()) % 7;
+            today
+                + Duration::days(if days_ahead == 0 {
+                    7
+                } else {
+                    days_ahead.into()
+                })
+        }
+    };
+    localize(
+        date.and_time(time.unwrap_or(NaiveTime::MIN)),
+        now.timezone(),
+    )
+}
+
+fn parse_absolute_date(text: &str, now: DateTime<Tz>) -> Option<DateTime<Utc>> {
+    let (day, time) = split_time(text)?;
+    let time = time.unwrap_or(NaiveTime::MIN);
+
+    if let Some(date) = DATE_FORMATS
+        .iter()
+        .find_map(|format| NaiveDate::parse_from_str(day, format).ok())
+    {
+        return localize(date.and_time(time), now.timezone());
+    }
+
+    let year = now.year();
+    let date = YEARLESS_DATE_FORMATS.iter().find_map(|format| {
+        NaiveDate::parse_from_str(&format!("{} {}", day, year), &format!("{} %Y", format)).ok()
+    })?;
+    let date = match date < now.date_naive() {
+        true => date.with_year(year + 1)?,
+        false => date,
+    };
+    localize(date.and_time(time), now.timezone())
+}
+
+/// Splits an optional trailing time of day ("18:00", "at 6pm") from the day
+fn split_time(text: &str) -> Option<(&str, Option<NaiveTime>)> {
+    let caps = TIME_OF_DAY.captures(text)?;
+    let day = caps.get(1)?.as_str();
+    let Some(hour) = caps.get(2) else {
+        return Some((day, None));
+    };
+
+    let mut hour = hour.as_str().parse::<u32>().ok()?;
+    let minute = caps
+        .get(3)
+        .map_or(Some(0), |minute| minute.as_str().parse::<u32>().ok())?;
+    match caps.get(4).map(|suffix| suffix.as_str()) {
+        Some(_) if hour == 0 || hour > 12 => return None,
+        Some("am") if hour == 12 => hour = 0,
+        Some("pm") if hour != 12 => hour += 12,
+        _ => {}
+    }
+    // without a minute or am/pm a trailing number is part of the date, e.g. "1 may 20"
+    if caps.get(3).is_none() && caps.get(4).is_none() {
+        return Some((text, None));
+    }
+    Some((day, Some(NaiveTime::from_hms_opt(hour, minute, 0)?)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Wednesday 2024-05-01 12:00 UTC
+    fn now() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 5, 1, 12, 0, 0).unwrap()
+    }
+
+    fn utc(year: i32, month: u32, day: u32, hour: u32, minute: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(year, month, day, hour, minute, 0)
+            .unwrap()
+    }
+
+    fn parse(text: &str) -> Result<DateTime<Utc>, String> {
+        parse_date(text, now(), Tz::UTC)
+    }
+
+    #[test]
+    fn parses_iso_dates() {
+        assert_eq!(parse("2024-05-10T18:00:00Z"), Ok(utc(2024, 5, 10, 18, 0)));
+        assert_eq!(
+            parse("2024-05-10T18:00:00+02:00"),
+            Ok(utc(2024, 5, 10, 16, 0))
+        );
+        assert_eq!(parse("2024-05-10T18:00"), Ok(utc(2024, 5, 10, 18, 0)));
+        assert_eq!(parse("2024-05-10 18:00"), Ok(utc(2024, 5, 10, 18, 0)));
+        assert_eq!(parse("2024-05-10"), Ok(utc(2024, 5, 10, 0, 0)));
+    }
+
+    #[test]
+    fn parses_day_month_dates() {
+        assert_eq!(parse("01/06/2024"), Ok(utc(2024, 6, 1, 0, 0)));
+        assert_eq!(parse("1.6.2024 9:30"), Ok(utc(2024, 6, 1, 9, 30)));
+        assert_eq!(parse("1 June 2024"), Ok(utc(2024, 6, 1, 0, 0)));
+        assert_eq!(parse("June 1, 2024"), Ok(utc(2024, 6, 1, 0, 0)));
+        assert_eq!(parse("10/05 18:00"), Ok(utc(2024, 5, 10, 18, 0)));
+        // a day that already passed this year is next year's
+        assert_eq!(parse("1 march"), Ok(utc(2025, 3, 1, 0, 0)));
+        assert!(parse("31/02/2024").is_err());
+        assert!(parse("someday").is_err());
+    }
+
+    #[test]
+    fn parses_relative_dates() {
+        assert_eq!(parse("in 3 days"), Ok(utc(2024, 5, 4, 12, 0)));
+        assert_eq!(parse("in 1 week"), Ok(utc(2024, 5, 8, 12, 0)));
+        assert_eq!(parse("in 90 minutes"), Ok(utc(2024, 5, 1, 13, 30)));
+        assert_eq!(parse("today 18:00"), Ok(utc(2024, 5, 1, 18, 0)));
+        assert_eq!(parse("Tomorrow at 6pm"), Ok(utc(2024, 5, 2, 18, 0)));
+        assert_eq!(parse("next friday"), Ok(utc(2024, 5, 3, 0, 0)));
+        // the coming wednesday, not today
+        assert_eq!(parse("wednesday 9:00"), Ok(utc(2024, 5, 8, 9, 0)));
+        // too far ahead for a date
+        assert!(parse("in 100000000 days").is_err());
+        assert!(parse("in 9999999999999999 minutes").is_err());
+        assert!(parse("in 99999999999999999999 weeks").is_err());
+    }
+
+    #[test]
+    fn reads_dates_in_the_time_zone() {
+        let new_york = Tz::America__New_York;
+        assert_eq!(
+            parse_date("2024-05-10 18:00", now(), new_york),
+            Ok(utc(2024, 5, 10, 22, 0))
+        );
+        // a zone written after the date wins over the chat's
+        assert_eq!(
+            parse_date("2024-05-10 18:00 Europe/Berlin", now(), new_york),
+            Ok(utc(2024, 5, 10, 16, 0))
+        );
+        // "tomorrow" is the day after the local date, which is still April 30 in Los Angeles
+        // at 02:00 UTC on May 1
+        let late = Utc.with_ymd_and_hms(2024, 5, 1, 2, 0, 0).unwrap();
+        assert_eq!(
+            parse_date("tomorrow 8:00", late, Tz::America__Los_Angeles),
+            Ok(utc(2024, 5, 1, 15, 0))
+        );
+        // 02:30 doesn't exist in Berlin when the clocks move forward
+        assert!(parse("2024-03-31 02:30 Europe/Berlin").is_err());
+    }
+
+    #[test]
+    fn splits_the_time_of_day() {
+        let time = |hour, minute| NaiveTime::from_hms_opt(hour, minute, 0);
+        assert_eq!(split_time("tomorrow"), Some(("tomorrow", None)));
+        assert_eq!(
+            split_time("tomorrow 18:00"),
+            Some(("tomorrow", time(18, 0)))
+        );
+        assert_eq!(split_time("friday, at 7:15"), Some(("friday", time(7, 15))));
+        assert_eq!(split_time("friday 12am"), Some(("friday", time(0, 0))));
+        assert_eq!(split_time("friday 12pm"), Some(("friday", time(12, 0))));
+        // a trailing number alone is part of the date
+        assert_eq!(split_time("1 may 20"), Some(("1 may 20", None)));
+        assert_eq!(split_time("friday 13pm"), None);
+        assert_eq!(split_time("friday 25:00"), None);
+    }
+
+    #[test]
+    fn checks_the_voting_window() {
+        let start = Some(utc(2024, 5, 2, 0, 0));
+        let end = Some(utc(2024, 5, 9, 0, 0));
+        assert_eq!(check_voting_window(start, end, now(), Tz::UTC), Ok(()));
+        assert_eq!(check_voting_window(None, None, now(), Tz::UTC), Ok(()));
+        assert_eq!(
+            check_voting_window(None, Some(utc(2024, 4, 30, 0, 0)), now(), Tz::UTC),
+            Err("The Expiration Date 2024-04-30 00:00 UTC is in the past".to_string())
+        );
+        assert_eq!(
+            check_voting_window(end, start, now(), Tz::UTC),
+            Err(
+                "The Expiration Date 2024-05-02 00:00 UTC has to be after the Starting Date \
+2024-05-09 00:00 UTC"
+                    .to_string()
+            )
+        );
+    }
+}
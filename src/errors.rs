@@ -7,11 +7,11 @@ pub enum TgError {
     AnyhowError(anyhow::Error),
     Parse(String),
     TeloxideRequest(teloxide::RequestError),
-    UnmatchedQuery(teloxide::types::CallbackQuery),
+    UnmatchedQuery(Box<teloxide::types::CallbackQuery>),
     TeloxideInMemStorageError(InMemStorageError),
-    NoQueryData(teloxide::types::CallbackQuery),
-    NoQueryMessage(teloxide::types::CallbackQuery),
-    UserNotFound(teloxide::types::Message),
+    NoQueryData(Box<teloxide::types::CallbackQuery>),
+    NoQueryMessage(Box<teloxide::types::CallbackQuery>),
+    UserNotFound(Box<teloxide::types::Message>),
 }
 
 impl fmt::Display for TgError {
@@ -1,30 +1,86 @@
-use super::dialogue_handlers::DialogueState;
-use crate::consts::BOT_NAME;
+use super::delete_up_to_messages;
+use super::dialogue_handlers::{
+    fill_in_draft_field, prompt_draft_field, DialogueState, DraftField,
+};
+use super::inline_handlers::edit_inline_proposal_card;
+use super::poll_handlers::{open_proposal_poll, stop_proposal_poll, sync_poll_tally};
+use super::{announce_result, edit_proposal_card, find_draft, pin_proposal_card, publish_proposal};
+use crate::consts::{BALLOT, BOT_NAME, DESCRIPTION, EXPIRATION_DATE, STARTING_DATE, TAGS, TITLE};
+use crate::consts::{COMMENTS_PER_PAGE, PROPOSALS_PER_PAGE, SEARCH};
+use crate::dates::{chat_timezone, check_voting_window, parse_date, DATE_FORMAT};
 use crate::errors::TgError;
+use crate::ical::proposals_calendar;
+use crate::keyboards::calendar_keyboard::{
+    calendar_keyboard, hour_keyboard, minute_keyboard, CalendarKeyboard,
+};
 use crate::keyboards::create_new_proposal_keyboard::new_proporsal_keyboard;
-use crate::keyboards::create_new_proposal_keyboard::CreateNewProposalKeyboard;
-use crate::keyboards::menu_keyboard;
+use crate::keyboards::create_new_proposal_keyboard::template_picker_keyboard;
 use crate::keyboards::see_proposals_keyboard::new_see_proporsal_keyboard;
-use crate::keyboards::see_proposals_keyboard::SeeProposalsKeyboard;
+use crate::keyboards::see_proposals_keyboard::{
+    browse_data, browser_keyboard, category_filter_keyboard, sort_rows,
+};
+use crate::keyboards::see_proposals_keyboard::{discussion_keyboard, veto_prompt_keyboard};
+use crate::keyboards::{callback_data, menu_keyboard};
 use crate::messages;
+use crate::messages::extract_field;
+use crate::messages::get_browser_message;
+use crate::messages::get_discussion_message;
+use crate::messages::get_proposal_message;
+use crate::messages::get_result_message;
+use crate::messages::get_search_results_message;
 use crate::messages::get_welcome_message;
+use crate::messages::parse_tags;
+use crate::messages::proposal_link;
+use crate::search::{search_proposals, SearchQuery};
+use crate::storage::Ballot;
+use crate::storage::Draft;
+use crate::storage::PendingVeto;
 use crate::storage::Proposal;
+use crate::storage::ProposalSort;
+use crate::storage::ProposalStatus;
+use crate::storage::TgChatSettingsStorage;
+use crate::storage::TgCommentStorage;
+use crate::storage::TgDraftStorage;
 use crate::storage::TgMessage;
 use crate::storage::TgMessageStorage;
 use crate::storage::TgProposalStorage;
-use crate::storage::GLOBAL_CREATE_PROPOSAL_STORAGE;
+use crate::storage::TgSavedSearchStorage;
+use crate::storage::TgUserSettingsStorage;
+use crate::storage::TgVetoStorage;
+use crate::storage::GLOBAL_CHAT_SETTINGS_STORAGE;
+use crate::storage::GLOBAL_COMMENT_STORAGE;
+use crate::storage::GLOBAL_DRAFT_STORAGE;
 use crate::storage::GLOBAL_MAIN_MENU_STORAGE;
 use crate::storage::GLOBAL_PROPOSAL_STORAGE;
+use crate::storage::GLOBAL_SAVED_SEARCH_STORAGE;
+use crate::storage::GLOBAL_USER_SETTINGS_STORAGE;
+use crate::storage::GLOBAL_VETO_STORAGE;
+use crate::templates::{chat_templates, draft_template, ProposalTemplate};
+use crate::utils::check_eligibility;
 use crate::utils::delete_previous_messages;
-use regex::Regex;
+use crate::utils::in_member_chats;
+use crate::utils::is_chat_admin;
+use crate::utils::user_name;
+use crate::utils::{topic, InTopic};
+use chrono::Utc;
 use std::sync::Arc;
 use teloxide::dispatching::dialogue::InMemStorage;
 use teloxide::dispatching::dialogue::Storage;
-use teloxide::payloads::{EditMessageTextSetters, SendMessageSetters};
+use teloxide::payloads::{
+    AnswerCallbackQuerySetters, EditMessageReplyMarkupSetters, EditMessageTextSetters,
+    SendMessageSetters,
+};
 use teloxide::prelude::Requester;
-use teloxide::types::{CallbackQuery, MediaKind, Message, MessageKind, ParseMode};
+use teloxide::types::{
+    CallbackQuery, ChatId, InlineKeyboardMarkup, InputFile, Message, ParseMode, UserId,
+};
 use teloxide::Bot;
 
+/// Answer to clicks on a draft that was submitted, closed or replaced by a newer one
+const INACTIVE_DRAFT: &str = "This draft is no longer active";
+
+const SEARCH_USAGE: &str = "Usage: /search <words> with optional status:passed (or sponsors, active, rejected, vetoed), from:<date> and to:<date>, e.g. /search budget status:passed from:2024-01-01";
+
 /// Upon a user clicks the "Main Menu", it'll clear the text and show the menu again
 pub async fn handle_menu_callback(bot: &Bot, q: &CallbackQuery) -> Result<(), TgError> {
     let keyboard = menu_keyboard();
@@ -34,229 +90,1020 @@ pub async fn handle_menu_callback(bot: &Bot, q: &CallbackQuery) -> Result<(), Tg
 
         let message_sent = bot
             .send_message(chat.id, welcome_msg)
+            .in_topic(q.message.as_ref().and_then(topic))
             .parse_mode(ParseMode::MarkdownV2)
             .reply_markup(keyboard)
             .await?;
         let message_sent = Arc::new(message_sent);
 
         // Updates the GLOBAL_STORAGE
-        let _user_name = message_sent
-            .clone()
-            .from()
-            .and_then(|user| user.username.as_ref())
-            .and_then(|user_name| {
-                let message = TgMessage {
-                    chat_id: message_sent.chat.id,
-                    message_id: message_sent.id,
-                    message: message_sent.clone(),
-                };
-                GLOBAL_MAIN_MENU_STORAGE.insert(user_name.to_string(), message);
-                Some(user_name)
-            });
-
-        let last_message_id = message_sent.id;
-        let _ = delete_previous_messages(bot, chat.id.0, last_message_id.0 - 1, 20).await?;
+        let message = TgMessage {
+            chat_id: message_sent.chat.id,
+            message_id: message_sent.id,
+            message: message_sent.clone(),
+        };
+        GLOBAL_MAIN_MENU_STORAGE.insert(user_name(&q.from), message);
+
+        // in groups the previous messages belong to other members
+        if chat.is_private() {
+            let last_message_id = message_sent.id;
+            delete_previous_messages(bot, chat.id.0, last_message_id.0 - 1, 20).await?;
+        }
     };
     Ok(())
 }
 
-pub async fn handle_submit_proposal_callback(bot: &Bot, q: &CallbackQuery) -> Result<(), TgError> {
+/// Turns the clicking member's draft into a proposal and publishes it to the chat the draft
+/// was started from
+pub async fn handle_submit_proposal_callback(
+    bot: &Bot,
+    q: &CallbackQuery,
+    template_index: usize,
+) -> Result<(), TgError> {
     let keyboard = menu_keyboard();
-    bot.answer_callback_query(&q.id).await?;
-    if let Some(Message { chat, kind, .. }) = &q.message {
-        let welcome_msg = get_welcome_message();
+    let (Some(Message { chat, .. }), Some(draft)) = (&q.message, find_draft(q)) else {
+        bot.answer_callback_query(&q.id)
+            .text(INACTIVE_DRAFT)
+            .await?;
+        return Ok(());
+    };
+    if let Err(reason) = check_eligibility(bot, draft.chat_id, q.from.id, "propose").await {
+        bot.answer_callback_query(&q.id)
+            .text(reason)
+            .show_alert(true)
+            .await?;
+        return Ok(());
+    }
+    let template = match draft_template(&draft, template_index) {
+        Ok(template) => template,
+        Err(reason) => {
+            GLOBAL_DRAFT_STORAGE.remove(q.from.id);
+            bot.answer_callback_query(&q.id)
+                .text(reason)
+                .show_alert(true)
+                .await?;
+            return Ok(());
+        }
+    };
+    let welcome_msg = get_welcome_message();
 
-        let extract_text = |kind: &MessageKind| -> Option<String> {
-            if let MessageKind::Common(common) = kind {
-                if let MediaKind::Text(media_text) = &common.media_kind {
-                    return Some(media_text.text.clone());
-                };
+    let values = template
+        .fields
+        .iter()
+        .map(|field| (field.name.clone(), extract_field(&draft.text, &field.name)))
+        .collect::<Vec<_>>();
+    let missing = template
+        .fields
+        .iter()
+        .zip(&values)
+        .filter(|(field, (_, value))| field.required && value.is_empty())
+        .map(|(field, _)| field.name.as_str())
+        .collect::<Vec<_>>();
+    if !missing.is_empty() {
+        bot.answer_callback_query(&q.id)
+            .text(format!("Please fill in: {}", missing.join(", ")))
+            .show_alert(true)
+            .await?;
+        return Ok(());
+    }
+
+    // the built-in fields have their own place in the proposal, the rest are kept as is
+    let now = Utc::now();
+    let timezone = chat_timezone(draft.chat_id);
+    let mut title = template.name.clone();
+    let mut description = String::new();
+    let mut starting_date = None;
+    let mut expiration_date = None;
+    let mut tags = vec![];
+    let mut ballot = Ballot::default();
+    let mut fields = vec![];
+    let mut problems = vec![];
+    for (name, value) in values {
+        match name.as_str() {
+            TITLE if !value.is_empty() => title = value,
+            DESCRIPTION => description = value,
+            STARTING_DATE | EXPIRATION_DATE if !value.is_empty() => {
+                match parse_date(&value, now, timezone) {
+                    Ok(date) if name == STARTING_DATE => starting_date = Some(date),
+                    Ok(date) => expiration_date = Some(date),
+                    Err(reason) => problems.push(format!("{}: {}", name, reason)),
+                }
             }
-            None
-        };
-        let text = extract_text(kind).unwrap();
-
-        let extractor = |message: &str, field_name: &str| -> String {
-            let pattern = format!(r"{}: ([^\n]*)\n", field_name);
-            let re = Regex::new(&pattern.as_str()).unwrap();
-            re.captures(message)
-                .and_then(|caps| caps.get(1).map(|m| m.as_str().to_string()))
-                .unwrap_or_default()
-        };
+            TAGS => tags = parse_tags(&value),
+            BALLOT => ballot = Ballot::parse(&value),
+            _ if !value.is_empty() => fields.push((name, value)),
+            _ => {}
+        }
+    }
+    // alerts are short, the first problem is enough
+    let window = match problems.into_iter().next() {
+        Some(problem) => Err(problem),
+        None => check_voting_window(starting_date, expiration_date, now, timezone),
+    };
+    if let Err(reason) = window {
+        bot.answer_callback_query(&q.id)
+            .text(reason)
+            .show_alert(true)
+            .await?;
+        return Ok(());
+    }
 
-        let title = extractor(&text, "Title");
-        let description = extractor(&text, "Description");
-        let starting_date = extractor(&text, "Starting Date");
-        let expiration_date = extractor(&text, "Expiration Date");
-        let proposal = Proposal {
-            title,
-            description,
-            starting_date,
-            expiration_date,
-            vote: 0,
-        };
+    let sponsors_required = GLOBAL_CHAT_SETTINGS_STORAGE
+        .get(draft.chat_id)
+        .sponsor_threshold;
+    let status = match sponsors_required {
+        0 => ProposalStatus::Active,
+        _ => ProposalStatus::SeekingSponsors,
+    };
+    let author = user_name(&q.from);
+    let mut proposal = Proposal {
+        id: GLOBAL_PROPOSAL_STORAGE.next_id(),
+        chat_id: draft.chat_id,
+        author_id: Some(q.from.id),
+        author: author.clone(),
+        title,
+        description,
+        starting_date,
+        expiration_date,
+        fields,
+        vote: 0,
+        votes_against: 0,
+        voters: vec![],
+        ballot,
+        poll: None,
+        topic: None,
+        card: None,
+        card_pinned: false,
+        pinned_results: None,
+        tags,
+        status,
+        sponsors: vec![],
+        sponsors_required,
+        veto_reason: None,
+        history: vec![format!("Submitted by {}", author)],
+    };
+    bot.answer_callback_query(&q.id)
+        .text(format!("Proposal #{} published", proposal.id))
+        .await?;
+    GLOBAL_DRAFT_STORAGE.remove(q.from.id);
+    GLOBAL_PROPOSAL_STORAGE.insert(proposal.clone());
 
-        let message_sent = bot
-            .send_message(chat.id, welcome_msg)
-            .parse_mode(ParseMode::MarkdownV2)
-            .reply_markup(keyboard)
+    let message_sent = bot
+        .send_message(chat.id, welcome_msg)
+        .parse_mode(ParseMode::MarkdownV2)
+        .reply_markup(keyboard)
+        .await?;
+    let last_message_id = message_sent.id;
+    delete_previous_messages(bot, chat.id.0, last_message_id.0 - 1, 20).await?;
+
+    // only the finished proposal is posted to the chat
+    publish_proposal(bot, &mut proposal).await
+}
+
+/// Starts a new proposal draft in the member's private chat with the bot, so that only the
+/// finished proposal shows up in a group. When the chat defined its own templates the member
+/// picks one first
+pub async fn handle_new_proposal_callback(bot: &Bot, q: &CallbackQuery) -> Result<(), TgError> {
+    let Some(Message { chat, .. }) = &q.message else {
+        bot.answer_callback_query(&q.id).await?;
+        return Ok(());
+    };
+    if let Err(reason) = check_eligibility(bot, chat.id, q.from.id, "propose").await {
+        bot.answer_callback_query(&q.id)
+            .text(reason)
+            .show_alert(true)
             .await?;
-        let message_sent = Arc::new(message_sent);
+        return Ok(());
+    }
+    let author = q.from.id;
+    GLOBAL_DRAFT_STORAGE.insert(
+        author,
+        Draft {
+            chat_id: chat.id,
+            message_id: None,
+            text: String::new(),
+            template: None,
+        },
+    );
 
-        let _user_name = message_sent
-            .clone()
-            .from()
-            .and_then(|user| user.username.as_ref())
-            .and_then(|user_name| {
-                GLOBAL_PROPOSAL_STORAGE.insert(user_name.to_string(), proposal);
-                Some(user_name)
-            });
-
-        let last_message_id = message_sent.id;
-        let _ = delete_previous_messages(bot, chat.id.0, last_message_id.0 - 1, 20).await?;
+    let templates = chat_templates(chat.id);
+    let sent = match templates.len() {
+        1 => send_proposal_draft(bot, author, 0, &templates[0]).await,
+        _ => bot
+            .send_message(author, "Which template do you want to use?")
+            .reply_markup(template_picker_keyboard(&templates))
+            .await
+            .map(|_| ())
+            .map_err(TgError::from),
     };
+    match sent {
+        Ok(()) if chat.is_private() => {
+            bot.answer_callback_query(&q.id).await?;
+        }
+        Ok(()) => {
+            bot.answer_callback_query(&q.id)
+                .text("Your draft is waiting in your private chat with me")
+                .await?;
+        }
+        // members have to start a conversation before the bot can message them
+        Err(err) => {
+            log::warn!("could not send the draft to {}: {}", author, err);
+            GLOBAL_DRAFT_STORAGE.remove(author);
+            bot.answer_callback_query(&q.id)
+                .text(format!(
+                    "Open a private chat with @{} and press Start first, proposals are drafted there",
+                    BOT_NAME
+                ))
+                .show_alert(true)
+                .await?;
+        }
+    }
     Ok(())
 }
 
-pub async fn handle_new_proposal_callback(bot: &Bot, q: &CallbackQuery) -> Result<(), TgError> {
-    let keyboard = new_proporsal_keyboard(false, false, false, false)?;
+/// Starts the draft from the template picked by name, the chat's templates may have
+/// changed since the picker was sent
+pub async fn handle_template_callback(
+    bot: &Bot,
+    q: &CallbackQuery,
+    template_name: &str,
+) -> Result<(), TgError> {
+    let Some(draft) = GLOBAL_DRAFT_STORAGE.get(q.from.id) else {
+        bot.answer_callback_query(&q.id)
+            .text(INACTIVE_DRAFT)
+            .await?;
+        return Ok(());
+    };
+    let templates = chat_templates(draft.chat_id);
+    let Some(template_index) = templates
+        .iter()
+        .position(|template| template.name == template_name)
+    else {
+        bot.answer_callback_query(&q.id)
+            .text(format!("Template \"{}\" no longer exists", template_name))
+            .show_alert(true)
+            .await?;
+        return Ok(());
+    };
     bot.answer_callback_query(&q.id).await?;
-    if let Some(Message { chat, .. }) = &q.message {
-        let proposal_msg = messages::get_new_proposal_message();
+    send_proposal_draft(bot, q.from.id, template_index, &templates[template_index]).await?;
+    if let Some(Message { chat, id, .. }) = &q.message {
+        bot.delete_message(chat.id, *id).await?;
+    }
+    Ok(())
+}
 
-        let _ = bot
-            .send_message(chat.id, proposal_msg)
-            .parse_mode(ParseMode::MarkdownV2)
-            .reply_markup(keyboard)
+/// Sends the empty draft to its author and remembers it as their current draft
+async fn send_proposal_draft(
+    bot: &Bot,
+    author: UserId,
+    template_index: usize,
+    template: &ProposalTemplate,
+) -> Result<(), TgError> {
+    let keyboard = new_proporsal_keyboard(template_index, template, &[])?;
+    let proposal_msg = messages::get_new_proposal_message(template);
+
+    let message_sent = bot
+        .send_message(author, proposal_msg)
+        .parse_mode(ParseMode::MarkdownV2)
+        .reply_markup(keyboard)
+        .await?;
+    if let Some(mut draft) = GLOBAL_DRAFT_STORAGE.get(author) {
+        draft.message_id = Some(message_sent.id);
+        draft.text = message_sent.text().unwrap_or_default().to_string();
+        draft.template = Some(template.clone());
+        GLOBAL_DRAFT_STORAGE.insert(author, draft);
+    }
+    Ok(())
+}
+
+/// Discards the proposal draft
+pub async fn handle_close_draft_callback(bot: &Bot, q: &CallbackQuery) -> Result<(), TgError> {
+    bot.answer_callback_query(&q.id).await?;
+    if find_draft(q).is_some() {
+        GLOBAL_DRAFT_STORAGE.remove(q.from.id);
+    }
+    if let Some(Message { chat, id, .. }) = &q.message {
+        bot.delete_message(chat.id, *id).await?;
+    }
+    Ok(())
+}
+
+pub async fn handle_proposal_fields_callback(
+    bot: &Bot,
+    q: &CallbackQuery,
+    storage: Arc<InMemStorage<DialogueState>>,
+    template_index: usize,
+    field_index: usize,
+) -> Result<(), TgError> {
+    let (Some(Message { chat, .. }), Some(draft)) = (&q.message, find_draft(q)) else {
+        bot.answer_callback_query(&q.id)
+            .text(INACTIVE_DRAFT)
             .await?;
+        return Ok(());
     };
-    Ok(())
+    let template = match draft_template(&draft, template_index) {
+        Ok(template) => template,
+        Err(reason) => {
+            GLOBAL_DRAFT_STORAGE.remove(q.from.id);
+            bot.answer_callback_query(&q.id)
+                .text(reason)
+                .show_alert(true)
+                .await?;
+            return Ok(());
+        }
+    };
+    bot.answer_callback_query(&q.id).await?;
+
+    prompt_draft_field(
+        bot,
+        chat.id,
+        draft.chat_id,
+        storage,
+        (template_index, &template),
+        field_index,
+    )
+    .await
 }
 
-pub async fn handle_proposal_fields_callback<'a>(
+/// Walks through the calendar, then the hour and minute pickers, and fills the picked
+/// date into the draft
+pub async fn handle_calendar_callback(
     bot: &Bot,
-    state: DialogueState,
     q: &CallbackQuery,
     storage: Arc<InMemStorage<DialogueState>>,
-    callback_type: CreateNewProposalKeyboard<'a>,
+    action: CalendarKeyboard,
 ) -> Result<(), TgError> {
     bot.answer_callback_query(&q.id).await?;
+    let Some(Message { chat, id, .. }) = &q.message else {
+        return Ok(());
+    };
 
-    if let Some(msg) = &q.message {
-        let msg = Arc::new(msg);
-        let _user_name = msg
-            .clone()
-            .from()
-            .and_then(|user| user.username.as_ref())
-            .and_then(|user_name| {
-                let message = TgMessage {
-                    chat_id: msg.chat.id,
-                    message_id: msg.id,
-                    message: (*msg).clone().into(),
-                };
-                GLOBAL_CREATE_PROPOSAL_STORAGE.insert(user_name.to_string(), message);
-                Some(user_name)
-            });
-    }
+    let keyboard = match action {
+        CalendarKeyboard::Month(picker, month) => calendar_keyboard(picker, month),
+        CalendarKeyboard::Day(picker, date) => hour_keyboard(picker, date),
+        CalendarKeyboard::Hour(picker, date, hour) => minute_keyboard(picker, date, hour),
+        CalendarKeyboard::Minute(picker, date, hour, minute) => {
+            let Some(date) = date.and_hms_opt(hour, minute, 0) else {
+                return Ok(());
+            };
+            // the picked time is local to the chat the proposal is for
+            let Some(draft) = GLOBAL_DRAFT_STORAGE.get(q.from.id) else {
+                bot.delete_message(chat.id, *id).await?;
+                return Ok(());
+            };
+            let timezone = chat_timezone(draft.chat_id);
+            let value = format!("{} {}", date.format(DATE_FORMAT), timezone.name());
+            match fill_in_draft_field(bot, q.from.id, picker.template, picker.field, &value).await?
+            {
+                DraftField::Filled(draft_id) => {
+                    storage.remove_dialogue(chat.id).await?;
+                    delete_up_to_messages(bot, chat.id.0, id.0, draft_id.0).await?;
+                }
+                DraftField::Missing => {
+                    bot.delete_message(chat.id, *id).await?;
+                }
+                DraftField::Rejected => {}
+            }
+            return Ok(());
+        }
+        CalendarKeyboard::Cancel => {
+            storage.remove_dialogue(chat.id).await?;
+            bot.delete_message(chat.id, *id).await?;
+            return Ok(());
+        }
+        CalendarKeyboard::Ignore => return Ok(()),
+    };
+    bot.edit_message_reply_markup(chat.id, *id)
+        .reply_markup(keyboard)
+        .await?;
+    Ok(())
+}
 
+/// Opens the proposal list. When the chat defined categories, members first pick one to filter by
+pub async fn handle_see_proposals_callback(bot: &Bot, q: &CallbackQuery) -> Result<(), TgError> {
+    bot.answer_callback_query(&q.id).await?;
     if let Some(Message { chat, .. }) = &q.message {
-        storage.clone().update_dialogue(chat.id, state).await?;
-        match callback_type {
-            CreateNewProposalKeyboard::Title(_) => {
-                bot.send_message(chat.id, "Enter the proposal Title")
-                    .await?;
-                storage
-                    .update_dialogue(chat.id, DialogueState::TitleReceived)
-                    .await?;
-            }
-            CreateNewProposalKeyboard::Description(_) => {
-                bot.send_message(chat.id, "Enter the proposal Description")
-                    .await?;
-                storage
-                    .update_dialogue(chat.id, DialogueState::DescriptionReceived)
-                    .await?;
-            }
-            CreateNewProposalKeyboard::StartingDate(_) => {
-                bot.send_message(chat.id, "Enter the proposal Starting Date")
-                    .await?;
-                storage
-                    .update_dialogue(chat.id, DialogueState::StartingDateReceived)
+        let thread = q.message.as_ref().and_then(topic);
+        let categories = GLOBAL_CHAT_SETTINGS_STORAGE.get(chat.id).categories;
+        match categories.is_empty() {
+            true => {
+                let sort = GLOBAL_USER_SETTINGS_STORAGE.get(q.from.id).proposal_sort;
+                let (text, keyboard) = proposal_browser(chat.id, 0, sort, None);
+                bot.send_message(chat.id, text)
+                    .in_topic(thread)
+                    .reply_markup(keyboard)
                     .await?;
             }
-            CreateNewProposalKeyboard::ExpirationDate(_) => {
-                bot.send_message(chat.id, "Enter the proposal Expiration Date")
-                    .await?;
-                storage
-                    .update_dialogue(chat.id, DialogueState::ExpirationDateReceived)
+            false => {
+                bot.send_message(chat.id, "Which category do you want to see?")
+                    .in_topic(thread)
+                    .reply_markup(category_filter_keyboard(&categories))
                     .await?;
             }
-            _ => {}
         }
+    };
+    Ok(())
+}
+
+/// Turns the category picker into the browser of the proposals tagged with the picked
+/// category, or of all of them, in the order the member prefers
+pub async fn handle_filter_proposals_callback(
+    bot: &Bot,
+    q: &CallbackQuery,
+    category: Option<String>,
+) -> Result<(), TgError> {
+    let sort = GLOBAL_USER_SETTINGS_STORAGE.get(q.from.id).proposal_sort;
+    handle_browse_callback(bot, q, 0, sort, category).await
+}
+
+/// Shows another page of the proposal browser, editing it in place
+pub async fn handle_browse_callback(
+    bot: &Bot,
+    q: &CallbackQuery,
+    page: usize,
+    sort: ProposalSort,
+    category: Option<String>,
+) -> Result<(), TgError> {
+    bot.answer_callback_query(&q.id).await?;
+    if let Some(Message { chat, id, .. }) = &q.message {
+        let (text, keyboard) = proposal_browser(chat.id, page, sort, category.as_deref());
+        bot.edit_message_text(chat.id, *id, text)
+            .reply_markup(keyboard)
+            .await?;
     }
     Ok(())
 }
 
-pub async fn handle_see_proposals_callback(bot: &Bot, q: &CallbackQuery) -> Result<(), TgError> {
+/// Remembers the order the member picked and lists the browser again from its first page
+pub async fn handle_sort_callback(
+    bot: &Bot,
+    q: &CallbackQuery,
+    sort: ProposalSort,
+    shown: ProposalSort,
+    category: Option<String>,
+) -> Result<(), TgError> {
+    let mut settings = GLOBAL_USER_SETTINGS_STORAGE.get(q.from.id);
+    settings.proposal_sort = sort;
+    GLOBAL_USER_SETTINGS_STORAGE.insert(q.from.id, settings);
+    // the browser would not change, and Telegram refuses edits that change nothing
+    if sort == shown {
+        bot.answer_callback_query(&q.id)
+            .text(format!("Already listed {}", sort))
+            .await?;
+        return Ok(());
+    }
+    handle_browse_callback(bot, q, 0, sort, category).await
+}
+
+/// Posts the card of a proposal picked in the browser
+pub async fn handle_open_proposal_callback(
+    bot: &Bot,
+    q: &CallbackQuery,
+    proposal_id: u64,
+) -> Result<(), TgError> {
+    let Some(Message { chat, .. }) = &q.message else {
+        bot.answer_callback_query(&q.id).await?;
+        return Ok(());
+    };
+    let Some(proposal) = GLOBAL_PROPOSAL_STORAGE
+        .get_by_id(proposal_id)
+        .filter(|proposal| proposal.chat_id == chat.id)
+    else {
+        bot.answer_callback_query(&q.id)
+            .text(format!("Proposal #{} no longer exists", proposal_id))
+            .await?;
+        return Ok(());
+    };
     bot.answer_callback_query(&q.id).await?;
-    if let Some(Message { chat, .. }) = &q.message {
-        if let Some(proposals) = GLOBAL_PROPOSAL_STORAGE.get(BOT_NAME.to_string()) {
-            for proposal in proposals {
-                let keyboard = new_see_proporsal_keyboard()?;
-                let msg = format!(
-                    "Title: {}\nDescription: {}\nStarting Date: {}\nExpiration Date: {}\nVotes: {}",
-                    proposal.title,
-                    proposal.description,
-                    proposal.starting_date,
-                    proposal.expiration_date,
-                    proposal.vote
-                );
-
-                let _message_sent = bot
-                    .send_message(chat.id, msg)
+
+    let msg = get_proposal_message(
+        &proposal,
+        GLOBAL_COMMENT_STORAGE.count(proposal.id),
+        chat_timezone(chat.id),
+    );
+    bot.send_message(chat.id, msg)
+        .in_topic(q.message.as_ref().and_then(topic))
+        .parse_mode(ParseMode::MarkdownV2)
+        .reply_markup(new_see_proporsal_keyboard(&proposal)?)
+        .await?;
+    Ok(())
+}
+
+/// Text and keyboard of a page of the browser over the chat's proposals in the given order,
+/// optionally only those tagged with a category
+fn proposal_browser(
+    chat_id: ChatId,
+    page: usize,
+    sort: ProposalSort,
+    category: Option<&str>,
+) -> (String, InlineKeyboardMarkup) {
+    let mut proposals = GLOBAL_PROPOSAL_STORAGE
+        .all()
+        .into_iter()
+        .filter(|proposal| {
+            proposal.chat_id == chat_id
+                && category.is_none_or(|category| proposal.tags.iter().any(|tag| tag == category))
+        })
+        .collect::<Vec<_>>();
+    sort.sort(&mut proposals, Utc::now());
+
+    let pages = proposals.len().div_ceil(PROPOSALS_PER_PAGE).max(1);
+    // proposals can move to other pages while the browser is open
+    let page = page.min(pages - 1);
+    let shown = proposals
+        .iter()
+        .skip(page * PROPOSALS_PER_PAGE)
+        .take(PROPOSALS_PER_PAGE)
+        .cloned()
+        .collect::<Vec<_>>();
+    let text = get_browser_message(&shown, page, pages, category, sort);
+    let ids = shown.iter().map(|proposal| proposal.id).collect::<Vec<_>>();
+    let mut keyboard =
+        browser_keyboard(&ids, page, pages, |page| browse_data(page, sort, category));
+    if !proposals.is_empty() {
+        keyboard
+            .inline_keyboard
+            .splice(0..0, sort_rows(sort, category));
+    }
+    (text, keyboard)
+}
+
+/// Answers /search with the first page of results, which can be paged through afterwards
+pub async fn send_search_results(bot: &Bot, msg: &Message, query: &str) -> Result<(), TgError> {
+    let user_id = msg
+        .from()
+        .map_or(UserId(msg.chat.id.0 as u64), |user| user.id);
+    let (text, keyboard) = match search_results(bot, msg.chat.id, user_id, query, 0).await {
+        Ok(results) => results,
+        Err(reason) => {
+            bot.send_message(msg.chat.id, format!("{}. {}", reason, SEARCH_USAGE))
+                .in_topic(topic(msg))
+                .await?;
+            return Ok(());
+        }
+    };
+    let sent = bot
+        .send_message(msg.chat.id, text)
+        .in_topic(topic(msg))
+        .reply_markup(keyboard)
+        .await?;
+    GLOBAL_SAVED_SEARCH_STORAGE.insert(msg.chat.id, sent.id, query.to_string());
+    Ok(())
+}
+
+/// Shows another page of the search results in the clicked message. The search runs again,
+/// so the results reflect the proposals as they are now
+pub async fn handle_search_page_callback(
+    bot: &Bot,
+    q: &CallbackQuery,
+    page: usize,
+) -> Result<(), TgError> {
+    let Some(Message { chat, id, .. }) = &q.message else {
+        bot.answer_callback_query(&q.id).await?;
+        return Ok(());
+    };
+    let Some(query) = GLOBAL_SAVED_SEARCH_STORAGE.get(chat.id, *id) else {
+        bot.answer_callback_query(&q.id)
+            .text("This search has expired, search again")
+            .await?;
+        return Ok(());
+    };
+    bot.answer_callback_query(&q.id).await?;
+    if let Ok((text, keyboard)) = search_results(bot, chat.id, q.from.id, &query, page).await {
+        bot.edit_message_text(chat.id, *id, text)
+            .reply_markup(keyboard)
+            .await?;
+    }
+    Ok(())
+}
+
+/// Text and keyboard of a page of the results of a search in a chat. Groups search their own
+/// proposals, private chats those of every chat the member is in
+async fn search_results(
+    bot: &Bot,
+    chat_id: ChatId,
+    user_id: UserId,
+    query: &str,
+    page: usize,
+) -> Result<(String, InlineKeyboardMarkup), String> {
+    let timezone = chat_timezone(chat_id);
+    let parsed = SearchQuery::parse(query, Utc::now(), timezone)?;
+    let mut found = search_proposals(&parsed, |proposal| {
+        chat_id.is_user() || proposal.chat_id == chat_id
+    });
+    if chat_id.is_user() {
+        found = in_member_chats(bot, user_id, found).await;
+    }
+
+    let pages = found.len().div_ceil(PROPOSALS_PER_PAGE).max(1);
+    let page = page.min(pages - 1);
+    let shown = found
+        .iter()
+        .skip(page * PROPOSALS_PER_PAGE)
+        .take(PROPOSALS_PER_PAGE)
+        .cloned()
+        .collect::<Vec<_>>();
+    let text = get_search_results_message(query, &shown, found.len(), page, pages);
+    let ids = shown.iter().map(|proposal| proposal.id).collect::<Vec<_>>();
+    let keyboard = browser_keyboard(&ids, page, pages, |page| {
+        callback_data(SEARCH, &[page as u64])
+    });
+    Ok((text, keyboard))
+}
+
+/// Counts a 👍 or 👎 on an active proposal, from a card in a chat or one shared inline
+pub async fn handle_vote_callback(
+    bot: &Bot,
+    q: &CallbackQuery,
+    proposal_id: u64,
+    in_favour: bool,
+) -> Result<(), TgError> {
+    let Some(proposal) = GLOBAL_PROPOSAL_STORAGE.get_by_id(proposal_id) else {
+        bot.answer_callback_query(&q.id).await?;
+        return Ok(());
+    };
+    let refusal = proposal
+        .voting_refusal(Utc::now())
+        .or(match proposal.ballot {
+            Ballot::Poll { .. } => Some("This proposal is voted on in its poll"),
+            Ballot::Buttons => None,
+        });
+    if let Some(refusal) = refusal {
+        bot.answer_callback_query(&q.id).text(refusal).await?;
+        return Ok(());
+    }
+    // cards can be forwarded, shared inline or opened from a link, so check the voter
+    if let Err(reason) = check_eligibility(bot, proposal.chat_id, q.from.id, "vote").await {
+        bot.answer_callback_query(&q.id)
+            .text(reason)
+            .show_alert(true)
+            .await?;
+        return Ok(());
+    }
+    // the proposal may have closed during the check. Every member votes once, voting
+    // again the other way moves the vote
+    let voted = GLOBAL_PROPOSAL_STORAGE.modify(proposal_id, |proposal| {
+        if let Some(refusal) = proposal.voting_refusal(Utc::now()) {
+            return Err(refusal);
+        }
+        match proposal.vote(q.from.id, in_favour) {
+            true => Ok(proposal.clone()),
+            false => Err("You already voted this way"),
+        }
+    });
+    let proposal = match voted {
+        Some(Ok(proposal)) => proposal,
+        Some(Err(refusal)) => {
+            bot.answer_callback_query(&q.id).text(refusal).await?;
+            return Ok(());
+        }
+        None => {
+            bot.answer_callback_query(&q.id).await?;
+            return Ok(());
+        }
+    };
+    bot.answer_callback_query(&q.id)
+        .text("Your vote was counted")
+        .await?;
+
+    match (&q.message, &q.inline_message_id) {
+        (Some(Message { chat, id, .. }), _) => {
+            edit_proposal_card(bot, chat.id, *id, &proposal).await?
+        }
+        (None, Some(inline_message_id)) => {
+            edit_inline_proposal_card(bot, inline_message_id, &proposal).await?
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Lets an admin end the vote early and announces the result
+pub async fn handle_close_voting_callback(
+    bot: &Bot,
+    q: &CallbackQuery,
+    proposal_id: u64,
+) -> Result<(), TgError> {
+    let (Some(Message { chat, id, .. }), Some(proposal)) =
+        (&q.message, GLOBAL_PROPOSAL_STORAGE.get_by_id(proposal_id))
+    else {
+        bot.answer_callback_query(&q.id).await?;
+        return Ok(());
+    };
+    if !is_chat_admin(bot, proposal.chat_id, q.from.id).await? {
+        bot.answer_callback_query(&q.id)
+            .text("Only chat admins can close the vote")
+            .show_alert(true)
+            .await?;
+        return Ok(());
+    }
+    if proposal.status != ProposalStatus::Active {
+        bot.answer_callback_query(&q.id)
+            .text("This proposal is not open for voting")
+            .await?;
+        return Ok(());
+    }
+    bot.answer_callback_query(&q.id).await?;
+
+    let poll = stop_proposal_poll(bot, &proposal).await;
+    // the scheduler or another admin may have closed it in the meantime
+    let closed = GLOBAL_PROPOSAL_STORAGE.modify(proposal_id, |proposal| {
+        if proposal.status != ProposalStatus::Active {
+            return None;
+        }
+        if let Some(poll) = &poll {
+            sync_poll_tally(proposal, poll);
+        }
+        proposal.close(&user_name(&q.from));
+        Some(proposal.clone())
+    });
+    let Some(mut proposal) = closed.flatten() else {
+        return Ok(());
+    };
+    edit_proposal_card(bot, chat.id, *id, &proposal).await?;
+
+    let announcement = get_result_message(&proposal);
+    announce_result(bot, &mut proposal, announcement).await
+}
+
+/// Asks an admin for the written reason of a veto, the veto itself happens once it is received
+pub async fn handle_veto_callback(
+    bot: &Bot,
+    q: &CallbackQuery,
+    proposal_id: u64,
+) -> Result<(), TgError> {
+    let (Some(Message { chat, id, .. }), Some(proposal)) =
+        (&q.message, GLOBAL_PROPOSAL_STORAGE.get_by_id(proposal_id))
+    else {
+        bot.answer_callback_query(&q.id).await?;
+        return Ok(());
+    };
+    if !is_chat_admin(bot, proposal.chat_id, q.from.id).await? {
+        bot.answer_callback_query(&q.id)
+            .text("Only chat admins can veto proposals")
+            .show_alert(true)
+            .await?;
+        return Ok(());
+    }
+    if !matches!(
+        proposal.status,
+        ProposalStatus::Active | ProposalStatus::Passed
+    ) {
+        bot.answer_callback_query(&q.id)
+            .text("Only active or passed proposals can be vetoed")
+            .await?;
+        return Ok(());
+    }
+    bot.answer_callback_query(&q.id).await?;
+
+    let prompt = bot
+        .send_message(
+            chat.id,
+            format!(
+                "{}, enter the reason for vetoing Proposal #{}",
+                user_name(&q.from),
+                proposal_id
+            ),
+        )
+        .reply_markup(veto_prompt_keyboard(proposal_id))
+        .in_topic(q.message.as_ref().and_then(topic))
+        .await?;
+    // a veto clicked earlier in the chat is replaced
+    GLOBAL_VETO_STORAGE.insert(
+        chat.id,
+        q.from.id,
+        PendingVeto {
+            proposal_id,
+            card: *id,
+            prompt: prompt.id,
+        },
+    );
+    Ok(())
+}
+
+/// Drops the clicking admin's pending veto, only the admin who clicked Veto can cancel it
+pub async fn handle_cancel_veto_callback(
+    bot: &Bot,
+    q: &CallbackQuery,
+    proposal_id: u64,
+) -> Result<(), TgError> {
+    let Some(Message { chat, id, .. }) = &q.message else {
+        bot.answer_callback_query(&q.id).await?;
+        return Ok(());
+    };
+    match GLOBAL_VETO_STORAGE.get(chat.id, q.from.id) {
+        Some(veto) if veto.proposal_id == proposal_id && veto.prompt == *id => {
+            GLOBAL_VETO_STORAGE.remove(chat.id, q.from.id);
+            bot.answer_callback_query(&q.id).await?;
+            bot.edit_message_text(
+                chat.id,
+                *id,
+                format!("The veto of Proposal #{} was cancelled", proposal_id),
+            )
+            .await?;
+        }
+        _ => {
+            bot.answer_callback_query(&q.id)
+                .text("Only the admin who vetoed can cancel, and only while the veto waits for its reason")
+                .await?;
+        }
+    }
+    Ok(())
+}
+
+/// Adds the clicking user as a co-sponsor, opening the vote once the threshold is reached
+pub async fn handle_sponsor_callback(
+    bot: &Bot,
+    q: &CallbackQuery,
+    proposal_id: u64,
+) -> Result<(), TgError> {
+    let (Some(Message { chat, id, .. }), Some(proposal)) =
+        (&q.message, GLOBAL_PROPOSAL_STORAGE.get_by_id(proposal_id))
+    else {
+        bot.answer_callback_query(&q.id).await?;
+        return Ok(());
+    };
+
+    if let Some(refusal) = sponsor_refusal(&proposal, q.from.id) {
+        bot.answer_callback_query(&q.id).text(refusal).await?;
+        return Ok(());
+    }
+    // cards can be forwarded, so check the sponsor like a voter
+    if let Err(reason) = check_eligibility(bot, proposal.chat_id, q.from.id, "sponsor").await {
+        bot.answer_callback_query(&q.id)
+            .text(reason)
+            .show_alert(true)
+            .await?;
+        return Ok(());
+    }
+    // others may have sponsored it during the check
+    let sponsored = GLOBAL_PROPOSAL_STORAGE.modify(proposal_id, |proposal| {
+        if let Some(refusal) = sponsor_refusal(proposal, q.from.id) {
+            return Err(refusal);
+        }
+        let sponsor = user_name(&q.from);
+        proposal.history.push(format!("Sponsored by {}", sponsor));
+        proposal.sponsors.push((q.from.id, sponsor));
+        if proposal.sponsors.len() >= proposal.sponsors_required {
+            proposal.status = ProposalStatus::Active;
+            proposal.history.push("Voting opened".to_string());
+        }
+        Ok(proposal.clone())
+    });
+    let mut proposal = match sponsored {
+        Some(Ok(proposal)) => proposal,
+        Some(Err(refusal)) => {
+            bot.answer_callback_query(&q.id).text(refusal).await?;
+            return Ok(());
+        }
+        None => {
+            bot.answer_callback_query(&q.id).await?;
+            return Ok(());
+        }
+    };
+    bot.answer_callback_query(&q.id)
+        .text("Thanks for sponsoring this proposal")
+        .await?;
+    edit_proposal_card(bot, chat.id, *id, &proposal).await?;
+
+    // announced in the proposal's own chat, the card may have been forwarded elsewhere
+    if proposal.status == ProposalStatus::Active {
+        bot.send_message(
+            proposal.chat_id,
+            format!(
+                "Proposal #{} \"{}\" has enough sponsors and is now open for voting",
+                proposal.id, proposal.title
+            ),
+        )
+        .in_topic(proposal.topic)
+        .await?;
+        pin_proposal_card(bot, &mut proposal).await;
+        open_proposal_poll(bot, &mut proposal).await?;
+    }
+    Ok(())
+}
+
+/// Why the member can't sponsor the proposal, if they can't
+fn sponsor_refusal(proposal: &Proposal, sponsor: UserId) -> Option<&'static str> {
+    if proposal.status != ProposalStatus::SeekingSponsors {
+        Some("This proposal is no longer seeking sponsors")
+    } else if proposal.author_id == Some(sponsor) {
+        Some("You can't sponsor your own proposal")
+    } else if proposal
+        .sponsors
+        .iter()
+        .any(|(user_id, _)| *user_id == sponsor)
+    {
+        Some("You already sponsor this proposal")
+    } else {
+        None
+    }
+}
+
+/// Shows a page of the proposal's discussion thread. Opening the thread sends a new message,
+/// turning pages edits the thread message in place
+pub async fn handle_discuss_callback(
+    bot: &Bot,
+    q: &CallbackQuery,
+    proposal_id: u64,
+    page: Option<usize>,
+) -> Result<(), TgError> {
+    bot.answer_callback_query(&q.id).await?;
+    if let (Some(Message { chat, id, .. }), Some(proposal)) =
+        (&q.message, GLOBAL_PROPOSAL_STORAGE.get_by_id(proposal_id))
+    {
+        let comments = GLOBAL_COMMENT_STORAGE.get(proposal_id).unwrap_or_default();
+        let pages = comments.len().div_ceil(COMMENTS_PER_PAGE).max(1);
+        let current_page = page.unwrap_or(0).min(pages - 1);
+
+        let msg = get_discussion_message(&proposal, &comments, current_page);
+        let keyboard = discussion_keyboard(proposal_id, current_page, pages);
+        match page {
+            Some(_) => {
+                bot.edit_message_text(chat.id, *id, msg)
+                    .parse_mode(ParseMode::MarkdownV2)
+                    .reply_markup(keyboard)
+                    .await?;
+            }
+            None => {
+                bot.send_message(chat.id, msg)
+                    .in_topic(q.message.as_ref().and_then(topic))
                     .parse_mode(ParseMode::MarkdownV2)
                     .reply_markup(keyboard)
                     .await?;
             }
         }
-    };
+    }
     Ok(())
 }
 
-pub async fn handle_thumb_up_callback(
+/// Sends the proposal's voting window as an .ics file members can add to their calendar
+pub async fn handle_add_to_calendar_callback(
     bot: &Bot,
     q: &CallbackQuery,
-    _propoal_type: SeeProposalsKeyboard,
+    proposal_id: u64,
 ) -> Result<(), TgError> {
     bot.answer_callback_query(&q.id).await?;
-    if let Some(Message { kind, chat, id, .. }) = &q.message {
-        let extract_text = |kind: &MessageKind| -> Option<String> {
-            if let MessageKind::Common(common) = kind {
-                if let MediaKind::Text(media_text) = &common.media_kind {
-                    return Some(media_text.text.clone());
-                }
-            }
-            None
-        };
+    if let (Some(Message { chat, .. }), Some(proposal)) =
+        (&q.message, GLOBAL_PROPOSAL_STORAGE.get_by_id(proposal_id))
+    {
+        let calendar = proposals_calendar([&proposal], Utc::now());
+        bot.send_document(
+            chat.id,
+            InputFile::memory(calendar).file_name(format!("proposal-{}.ics", proposal.id)),
+        )
+        .in_topic(q.message.as_ref().and_then(topic))
+        .await?;
+    }
+    Ok(())
+}
 
-        let mut new_message: String = "".to_string();
-        let text = extract_text(kind).unwrap();
-        let re = Regex::new(r"Votes: (\d+)").unwrap();
-        if let Some(caps) = re.captures(&text) {
-            if let Some(match_) = caps.get(1) {
-                if let Ok(current_vote) = match_.as_str().parse::<i32>() {
-                    let incremented_vote = current_vote + 1;
-                    new_message = re
-                        .replace(&text, format!("Votes: {}", incremented_vote))
-                        .to_string();
-                }
-            }
-        }
+/// Posts the link that opens the proposal in a private chat with the bot
+pub async fn handle_share_callback(
+    bot: &Bot,
+    q: &CallbackQuery,
+    proposal_id: u64,
+) -> Result<(), TgError> {
+    bot.answer_callback_query(&q.id).await?;
+    if let (Some(Message { chat, .. }), Some(proposal)) =
+        (&q.message, GLOBAL_PROPOSAL_STORAGE.get_by_id(proposal_id))
+    {
+        bot.send_message(
+            chat.id,
+            format!("🔗 {}\n{}", proposal.title, proposal_link(proposal.id)),
+        )
+        .in_topic(q.message.as_ref().and_then(topic))
+        .await?;
+    }
+    Ok(())
+}
 
-        let keyboard = new_see_proporsal_keyboard()?;
-        bot.edit_message_text(chat.id, *id, new_message)
-            .parse_mode(ParseMode::MarkdownV2)
-            .reply_markup(keyboard)
+/// In a private chat the next message becomes a comment, in groups comments are made by replying
+pub async fn handle_comment_callback(
+    bot: &Bot,
+    q: &CallbackQuery,
+    proposal_id: u64,
+    storage: Arc<InMemStorage<DialogueState>>,
+) -> Result<(), TgError> {
+    match &q.message {
+        Some(Message { chat, .. }) if chat.is_private() => {
+            bot.answer_callback_query(&q.id).await?;
+            storage
+                .update_dialogue(chat.id, DialogueState::CommentReceived { proposal_id })
+                .await?;
+            bot.send_message(
+                chat.id,
+                format!("Enter your comment on Proposal #{}", proposal_id),
+            )
             .await?;
+        }
+        _ => {
+            bot.answer_callback_query(&q.id)
+                .text("Reply to the proposal card to leave a comment")
+                .show_alert(true)
+                .await?;
+        }
     }
     Ok(())
 }
@@ -0,0 +1,33 @@
+use crate::storage::{
+    TgAdminStorage, TgMembershipStorage, GLOBAL_ADMIN_STORAGE, GLOBAL_MEMBERSHIP_STORAGE,
+};
+use crate::TgError;
+use teloxide::types::ChatMemberUpdated;
+
+/// Keeps the cached chat admins in step with promotions, demotions and members leaving, and
+/// records when members join. Telegram only sends these updates to bots that administer the chat
+pub async fn chat_member_handler(update: ChatMemberUpdated) -> Result<(), TgError> {
+    let user_id = update.new_chat_member.user.id;
+    GLOBAL_ADMIN_STORAGE.update(
+        update.chat.id,
+        user_id,
+        update.new_chat_member.is_privileged(),
+    );
+    match (
+        update.old_chat_member.is_present(),
+        update.new_chat_member.is_present(),
+    ) {
+        (false, true) => GLOBAL_MEMBERSHIP_STORAGE.insert(update.chat.id, user_id, update.date),
+        (true, false) => {
+            GLOBAL_MEMBERSHIP_STORAGE.remove(update.chat.id, user_id);
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// The bot's own membership changed, so the cached admins may have missed updates
+pub async fn my_chat_member_handler(update: ChatMemberUpdated) -> Result<(), TgError> {
+    GLOBAL_ADMIN_STORAGE.remove(update.chat.id);
+    Ok(())
+}
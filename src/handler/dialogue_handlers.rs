@@ -1,20 +1,34 @@
-use crate::consts::{BOT_NAME, DESCRIPTION, EXPIRATION_DATE, STARTING_DATE, TITLE};
-use crate::handler::delete_up_to_messages;
-use crate::keyboards::add_emoji;
-use crate::messages::parse_message;
-use crate::storage::{TgMessage, TgMessageStorage, GLOBAL_CREATE_PROPOSAL_STORAGE};
+use crate::consts::{
+    BOT_NAME, COMMENTS_PER_PAGE, DESCRIPTION, EXPIRATION_DATE, STARTING_DATE, TAGS, TITLE,
+};
+use crate::dates::{chat_timezone, check_voting_window, parse_date};
+use crate::handler::poll_handlers::stop_proposal_poll;
+use crate::handler::{announce_result, delete_up_to_messages, edit_proposal_card};
+use crate::keyboards::calendar_keyboard::{calendar_keyboard, PickerField};
+use crate::keyboards::create_new_proposal_keyboard::new_proporsal_keyboard;
+use crate::keyboards::see_proposals_keyboard::discussion_keyboard;
+use crate::messages::{
+    extract_field, get_discussion_message, get_new_draft_text, parse_message, parse_tags,
+};
+use crate::storage::{
+    Comment, Draft, PendingVeto, Proposal, ProposalStatus, TgChatSettingsStorage, TgCommentStorage,
+    TgDraftStorage, TgProposalStorage, TgVetoStorage, GLOBAL_CHAT_SETTINGS_STORAGE,
+    GLOBAL_COMMENT_STORAGE, GLOBAL_DRAFT_STORAGE, GLOBAL_PROPOSAL_STORAGE, GLOBAL_VETO_STORAGE,
+};
+use crate::templates::{draft_template, FieldType, ProposalTemplate};
+use crate::utils::{check_eligibility, topic, user_name, InTopic};
 use crate::TgError;
-use teloxide::types::MessageKind;
+use chrono::Utc;
+use std::sync::Arc;
+use teloxide::utils::markdown::escape;
 use teloxide::{
-    dispatching::dialogue::{Dialogue, InMemStorage},
-    payloads::EditMessageTextSetters,
+    dispatching::dialogue::{Dialogue, InMemStorage, Storage},
+    payloads::{EditMessageTextSetters, SendMessageSetters},
     requests::Requester,
-    types::{InlineKeyboardButtonKind, MediaKind, Message, ParseMode},
+    types::{ChatId, Message, MessageId, ParseMode, User, UserId},
     Bot,
 };
 
-use super::find_keyboard_from_message;
-
 pub type ProposalPromptDialogue = Dialogue<DialogueState, InMemStorage<DialogueState>>;
 
 /// Dialogue state
@@ -23,10 +37,13 @@ pub type ProposalPromptDialogue = Dialogue<DialogueState, InMemStorage<DialogueS
 pub enum DialogueState {
     #[default]
     StartTitlePrompt,
-    TitleReceived,
-    DescriptionReceived,
-    StartingDateReceived,
-    ExpirationDateReceived,
+    FieldReceived {
+        template: usize,
+        field: usize,
+    },
+    CommentReceived {
+        proposal_id: u64,
+    },
 }
 
 pub async fn start_title_dialogue_handler(
@@ -34,227 +51,503 @@ pub async fn start_title_dialogue_handler(
     dialogue: ProposalPromptDialogue,
     msg: Message,
 ) -> Result<(), TgError> {
-    bot.send_message(msg.chat.id, "Enter the title").await?;
+    // group members talk among themselves, only private chats get a nudge
+    if msg.chat.is_private() {
+        bot.send_message(msg.chat.id, "Use /menu to create or see proposals")
+            .await?;
+    }
     dialogue.update(DialogueState::StartTitlePrompt).await?;
     Ok(())
 }
 
-pub async fn receive_title_handler(
+/// Fills the value typed for a draft field in, see `fill_in_draft_field`
+pub async fn receive_field_handler(
     bot: Bot,
     dialogue: ProposalPromptDialogue,
+    (template_index, field_index): (usize, usize),
     msg: Message,
 ) -> Result<(), TgError> {
-    let text = match msg.text() {
-        Some(t) => t,
+    let (text, user) = match (msg.text(), msg.from()) {
+        (Some(t), Some(user)) => (t, user),
         _ => {
             bot.send_message(msg.chat.id, "Send me plain text.").await?;
             return Ok(());
         }
     };
 
-    if let Some(menu) = GLOBAL_CREATE_PROPOSAL_STORAGE.get(BOT_NAME.to_string()) {
-        let extract_text = |tg_message: &TgMessage| -> Option<String> {
-            if let MessageKind::Common(common) = &tg_message.message.kind {
-                if let MediaKind::Text(media_text) = &common.media_kind {
-                    return Some(media_text.text.clone());
-                }
-            }
-            None
-        };
+    match fill_in_draft_field(&bot, user.id, template_index, field_index, text).await? {
+        DraftField::Filled(draft_id) => {
+            dialogue.exit().await?;
+            delete_up_to_messages(&bot, msg.chat.id.0, msg.id.0, draft_id.0).await?;
+        }
+        DraftField::Missing => dialogue.exit().await?,
+        DraftField::Rejected => {}
+    }
+    Ok(())
+}
 
-        let extracted_text = extract_text(&menu).unwrap();
-        let proposal_msg = parse_message(extracted_text.as_str(), Some(text), None, None, None);
-        let menu_msg = menu.message;
-        let msg_id = menu.message_id;
-        let keyboard = find_keyboard_from_message(&menu_msg)?;
-        let mut new_keyboard = keyboard.clone();
+/// Outcome of filling a value into the proposal draft
+pub(crate) enum DraftField {
+    /// The draft message was updated
+    Filled(MessageId),
+    /// The value was rejected and the user was told why
+    Rejected,
+    /// The template field or the draft no longer exists
+    Missing,
+}
 
-        let new_button_text = add_emoji(TITLE);
+/// Validates a value for a field of the author's draft against its template, re-prompting
+/// with the reason when it is rejected, and fills it into the draft
+pub(crate) async fn fill_in_draft_field(
+    bot: &Bot,
+    author: UserId,
+    template_index: usize,
+    field_index: usize,
+    text: &str,
+) -> Result<DraftField, TgError> {
+    let Some((mut draft, draft_id)) = GLOBAL_DRAFT_STORAGE
+        .get(author)
+        .and_then(|draft| Some((draft.clone(), draft.message_id?)))
+    else {
+        log::warn!("no draft for {}", author);
+        return Ok(DraftField::Missing);
+    };
+    let template = match draft_template(&draft, template_index) {
+        Ok(template) => template,
+        Err(reason) => {
+            GLOBAL_DRAFT_STORAGE.remove(author);
+            bot.send_message(author, reason).await?;
+            return Ok(DraftField::Missing);
+        }
+    };
+    let Some(field) = template.fields.get(field_index) else {
+        log::warn!(
+            "template field {}/{} not found",
+            template_index,
+            field_index
+        );
+        return Ok(DraftField::Missing);
+    };
 
-        if let Some(button) = new_keyboard
-            .inline_keyboard
-            .get_mut(1)
-            .and_then(|row| row.get_mut(0))
-        {
-            button.text = new_button_text.to_string();
-            button.kind = InlineKeyboardButtonKind::CallbackData(new_button_text.to_string());
+    let timezone = chat_timezone(draft.chat_id);
+    let mut value = match field.validate(text, timezone) {
+        Ok(value) => value,
+        Err(reason) => {
+            bot.send_message(author, format!("{}. Please try again.", reason))
+                .await?;
+            return Ok(DraftField::Rejected);
         }
+    };
 
-        // Edit the message with the new keyboard
-        bot.edit_message_text(msg.chat.id, msg_id, proposal_msg)
-            .parse_mode(ParseMode::MarkdownV2)
-            .reply_markup(new_keyboard)
+    // when the chat defined categories, every tag has to be one of them
+    if field.name == TAGS {
+        let tags = parse_tags(&value);
+        let categories = GLOBAL_CHAT_SETTINGS_STORAGE.get(draft.chat_id).categories;
+        if let Some(unknown) = tags
+            .iter()
+            .find(|tag| !categories.is_empty() && !categories.contains(tag))
+        {
+            bot.send_message(
+                author,
+                format!(
+                    "Unknown category \"{}\". Use a comma separated list of: {}",
+                    unknown,
+                    categories.join(", ")
+                ),
+            )
             .await?;
-        dialogue.exit().await?;
+            return Ok(DraftField::Rejected);
+        }
+        value = tags.join(", ");
+    }
 
-        let _ = delete_up_to_messages(&bot, msg.chat.id.0, msg.id.0, msg_id.0).await?;
-    } else {
-        log::warn!("message not found");
+    let proposal_msg = parse_message(&draft.text, &field.name, &value);
+
+    if field.name == STARTING_DATE || field.name == EXPIRATION_DATE {
+        let now = Utc::now();
+        let date = |name: &str| parse_date(&extract_field(&proposal_msg, name), now, timezone).ok();
+        let starting_date = date(STARTING_DATE);
+        let expiration_date = date(EXPIRATION_DATE);
+        if let Err(reason) = check_voting_window(starting_date, expiration_date, now, timezone) {
+            bot.send_message(author, format!("{}. Please try again.", reason))
+                .await?;
+            return Ok(DraftField::Rejected);
+        }
     }
-    Ok(())
+
+    let filled = template
+        .fields
+        .iter()
+        .map(|field| !extract_field(&proposal_msg, &field.name).is_empty())
+        .collect::<Vec<_>>();
+    let new_keyboard = new_proporsal_keyboard(template_index, &template, &filled)?;
+
+    // Edit the message with the new keyboard
+    bot.edit_message_text(author, draft_id, escape(&proposal_msg))
+        .parse_mode(ParseMode::MarkdownV2)
+        .reply_markup(new_keyboard)
+        .await?;
+    draft.text = proposal_msg;
+    GLOBAL_DRAFT_STORAGE.insert(author, draft);
+    Ok(DraftField::Filled(draft_id))
 }
 
-pub async fn receive_description_handler(
-    bot: Bot,
-    dialogue: ProposalPromptDialogue,
-    msg: Message,
+/// Asks the author in their private chat `author_chat` for a field of their draft for
+/// `draft_chat` and waits for the answer
+pub(crate) async fn prompt_draft_field(
+    bot: &Bot,
+    author_chat: ChatId,
+    draft_chat: ChatId,
+    storage: Arc<InMemStorage<DialogueState>>,
+    (template_index, template): (usize, &ProposalTemplate),
+    field_index: usize,
 ) -> Result<(), TgError> {
-    let text = match msg.text() {
-        Some(t) => t,
+    let Some(field) = template.fields.get(field_index) else {
+        return Ok(());
+    };
+
+    let categories = GLOBAL_CHAT_SETTINGS_STORAGE.get(draft_chat).categories;
+    let prompt = match field.name.as_str() {
+        TAGS if categories.is_empty() => "Enter the proposal Tags, separated by commas".to_string(),
+        TAGS => format!(
+            "Enter the proposal Tags, separated by commas. Categories: {}",
+            categories.join(", ")
+        ),
+        _ => field.prompt(),
+    };
+    match field.field_type {
+        // dates can also be picked from a calendar instead of typed
+        FieldType::Date => {
+            let today = Utc::now()
+                .with_timezone(&chat_timezone(draft_chat))
+                .date_naive();
+            let picker = PickerField {
+                template: template_index,
+                field: field_index,
+            };
+            bot.send_message(author_chat, format!("{}, or pick it below", prompt))
+                .reply_markup(calendar_keyboard(picker, today))
+                .await?;
+        }
         _ => {
-            bot.send_message(msg.chat.id, "Send me plain text.").await?;
-            return Ok(());
+            bot.send_message(author_chat, prompt).await?;
         }
+    }
+    storage
+        .update_dialogue(
+            author_chat,
+            DialogueState::FieldReceived {
+                template: template_index,
+                field: field_index,
+            },
+        )
+        .await?;
+    Ok(())
+}
+
+/// Fields filled in by `/propose Title | Description | start | end`, in order
+const PROPOSE_FIELDS: [&str; 4] = [TITLE, DESCRIPTION, STARTING_DATE, EXPIRATION_DATE];
+
+const PROPOSE_USAGE: &str = "Usage: /propose Title | Description | start | end, e.g. \
+/propose New logo | Vote on the new logo | tomorrow 18:00 | in 7 days. Leave parts out to fill them in later, \
+and don't use | inside the title, description or start";
+
+/// Reads `/propose Title | Description | start | end` into a draft of the default template in
+/// one go. Only the first three "|" separate parts, the last part takes the rest of the text.
+/// Every value is checked before the draft is sent to the author's private chat, and the
+/// first required field left out is asked for right away
+pub(crate) async fn propose_command(
+    bot: &Bot,
+    msg: &Message,
+    text: &str,
+    storage: Arc<InMemStorage<DialogueState>>,
+) -> Result<(), TgError> {
+    let Some(user) = msg.from() else {
+        return Ok(());
     };
+    let author = user.id;
+    // the last part takes the rest of the text, "|" included
+    let values = text
+        .splitn(PROPOSE_FIELDS.len(), '|')
+        .map(str::trim)
+        .collect::<Vec<_>>();
+    if text.trim().is_empty() {
+        bot.send_message(msg.chat.id, PROPOSE_USAGE)
+            .in_topic(topic(msg))
+            .await?;
+        return Ok(());
+    }
+    if let Err(reason) = check_eligibility(bot, msg.chat.id, author, "propose").await {
+        bot.send_message(msg.chat.id, reason)
+            .in_topic(topic(msg))
+            .await?;
+        return Ok(());
+    }
 
-    if let Some(menu) = GLOBAL_CREATE_PROPOSAL_STORAGE.get(BOT_NAME.to_string()) {
-        let extract_text = |tg_message: &TgMessage| -> Option<String> {
-            if let MessageKind::Common(common) = &tg_message.message.kind {
-                if let MediaKind::Text(media_text) = &common.media_kind {
-                    return Some(media_text.text.clone());
-                }
-            }
-            None
+    let template_index = 0;
+    let template = ProposalTemplate::default();
+    let timezone = chat_timezone(msg.chat.id);
+    let mut draft_text = get_new_draft_text(&template);
+    let mut problems = vec![];
+    for (name, value) in PROPOSE_FIELDS.iter().zip(&values) {
+        let Some(field) = template.fields.iter().find(|field| field.name == *name) else {
+            continue;
         };
-
-        let extracted_text = extract_text(&menu).unwrap();
-        let proposal_msg = parse_message(extracted_text.as_str(), None, Some(text), None, None);
-        let menu_msg = menu.message;
-        let msg_id = menu.message_id;
-        let keyboard = find_keyboard_from_message(&menu_msg)?;
-        let mut new_keyboard = keyboard.clone();
-
-        let new_button_text = add_emoji(DESCRIPTION);
-
-        if let Some(button) = new_keyboard
-            .inline_keyboard
-            .get_mut(2)
-            .and_then(|row| row.get_mut(0))
+        if value.is_empty() {
+            continue;
+        }
+        match field.validate(value, timezone) {
+            Ok(value) => draft_text = parse_message(&draft_text, name, &value),
+            Err(reason) => problems.push(reason),
+        }
+    }
+    if problems.is_empty() {
+        let now = Utc::now();
+        let date = |name: &str| parse_date(&extract_field(&draft_text, name), now, timezone).ok();
+        if let Err(reason) =
+            check_voting_window(date(STARTING_DATE), date(EXPIRATION_DATE), now, timezone)
         {
-            button.text = new_button_text.to_string();
-            button.kind = InlineKeyboardButtonKind::CallbackData(new_button_text.to_string());
+            problems.push(reason);
         }
+    }
+    if !problems.is_empty() {
+        bot.send_message(
+            msg.chat.id,
+            format!("{}.\n{}", problems.join(".\n"), PROPOSE_USAGE),
+        )
+        .in_topic(topic(msg))
+        .await?;
+        return Ok(());
+    }
 
-        // Edit the message with the new keyboard
-        bot.edit_message_text(msg.chat.id, msg_id, proposal_msg)
-            .parse_mode(ParseMode::MarkdownV2)
-            .reply_markup(new_keyboard)
+    let filled = template
+        .fields
+        .iter()
+        .map(|field| !extract_field(&draft_text, &field.name).is_empty())
+        .collect::<Vec<_>>();
+    let sent = bot
+        .send_message(author, escape(&draft_text))
+        .parse_mode(ParseMode::MarkdownV2)
+        .reply_markup(new_proporsal_keyboard(template_index, &template, &filled)?)
+        .await;
+    let draft = match sent {
+        Ok(draft) => draft,
+        // members have to start a conversation before the bot can message them
+        Err(err) => {
+            log::warn!("could not send the draft to {}: {}", author, err);
+            bot.send_message(
+                msg.chat.id,
+                format!(
+                    "Open a private chat with @{} and press Start first, proposals are drafted there",
+                    BOT_NAME
+                ),
+            )
+            .in_topic(topic(msg))
             .await?;
-        dialogue.exit().await?;
+            return Ok(());
+        }
+    };
+    GLOBAL_DRAFT_STORAGE.insert(
+        author,
+        Draft {
+            chat_id: msg.chat.id,
+            message_id: Some(draft.id),
+            text: draft_text,
+            template: Some(template.clone()),
+        },
+    );
+    if !msg.chat.is_private() {
+        bot.send_message(
+            msg.chat.id,
+            "Your draft is waiting in your private chat with me",
+        )
+        .in_topic(topic(msg))
+        .await?;
+    }
 
-        let _ = delete_up_to_messages(&bot, msg.chat.id.0, msg.id.0, msg_id.0).await?;
-    } else {
-        log::warn!("message not found");
+    let missing = template
+        .fields
+        .iter()
+        .zip(&filled)
+        .position(|(field, filled)| field.required && !filled);
+    match missing {
+        Some(field_index) => {
+            prompt_draft_field(
+                bot,
+                ChatId(author.0 as i64),
+                msg.chat.id,
+                storage,
+                (template_index, &template),
+                field_index,
+            )
+            .await
+        }
+        None => Ok(()),
     }
-    Ok(())
 }
 
-pub async fn receive_starting_date_handler(
+/// Stores a comment typed in a private chat after pressing the comment button
+pub async fn receive_comment_handler(
     bot: Bot,
     dialogue: ProposalPromptDialogue,
+    proposal_id: u64,
     msg: Message,
 ) -> Result<(), TgError> {
-    let text = match msg.text() {
-        Some(t) => t,
+    let (text, user) = match (msg.text(), msg.from()) {
+        (Some(t), Some(user)) => (t, user),
         _ => {
             bot.send_message(msg.chat.id, "Send me plain text.").await?;
             return Ok(());
         }
     };
 
-    if let Some(menu) = GLOBAL_CREATE_PROPOSAL_STORAGE.get(BOT_NAME.to_string()) {
-        let extract_text = |tg_message: &TgMessage| -> Option<String> {
-            if let MessageKind::Common(common) = &tg_message.message.kind {
-                if let MediaKind::Text(media_text) = &common.media_kind {
-                    return Some(media_text.text.clone());
-                }
-            }
-            None
-        };
-
-        let extracted_text = extract_text(&menu).unwrap();
-        let proposal_msg = parse_message(extracted_text.as_str(), None, None, Some(text), None);
-        let menu_msg = menu.message;
-        let msg_id = menu.message_id;
-        let keyboard = find_keyboard_from_message(&menu_msg)?;
-        let mut new_keyboard = keyboard.clone();
-
-        let new_button_text = add_emoji(STARTING_DATE);
+    if let Some(proposal) = GLOBAL_PROPOSAL_STORAGE.get_by_id(proposal_id) {
+        GLOBAL_COMMENT_STORAGE.insert(
+            proposal_id,
+            Comment {
+                author: user_name(user),
+                text: text.to_string(),
+            },
+        );
+
+        let comments = GLOBAL_COMMENT_STORAGE.get(proposal_id).unwrap_or_default();
+        let pages = comments.len().div_ceil(COMMENTS_PER_PAGE).max(1);
+        bot.send_message(
+            msg.chat.id,
+            get_discussion_message(&proposal, &comments, pages - 1),
+        )
+        .parse_mode(ParseMode::MarkdownV2)
+        .reply_markup(discussion_keyboard(proposal_id, pages - 1, pages))
+        .await?;
+    } else {
+        log::warn!("proposal {} not found", proposal_id);
+    }
+    dialogue.exit().await?;
+    Ok(())
+}
 
-        if let Some(button) = new_keyboard
-            .inline_keyboard
-            .get_mut(3)
-            .and_then(|row| row.get_mut(0))
-        {
-            button.text = new_button_text.to_string();
-            button.kind = InlineKeyboardButtonKind::CallbackData(new_button_text.to_string());
-        }
+/// Stores a reply to a proposal card or discussion thread as a comment, then refreshes the
+/// replied message so the new comment is reflected
+pub async fn receive_comment_reply_handler(
+    bot: Bot,
+    proposal_id: u64,
+    msg: Message,
+) -> Result<(), TgError> {
+    let (text, user, replied) = match (msg.text(), msg.from(), msg.reply_to_message()) {
+        (Some(t), Some(user), Some(replied)) => (t, user, replied),
+        _ => return Ok(()),
+    };
 
-        // Edit the message with the new keyboard
-        bot.edit_message_text(msg.chat.id, msg_id, proposal_msg)
+    if let Some(proposal) = GLOBAL_PROPOSAL_STORAGE.get_by_id(proposal_id) {
+        GLOBAL_COMMENT_STORAGE.insert(
+            proposal_id,
+            Comment {
+                author: user_name(user),
+                text: text.to_string(),
+            },
+        );
+
+        let comments = GLOBAL_COMMENT_STORAGE.get(proposal_id).unwrap_or_default();
+        let is_card = replied
+            .text()
+            .is_some_and(|text| text.starts_with("Proposal #"));
+        if is_card {
+            edit_proposal_card(&bot, msg.chat.id, replied.id, &proposal).await?;
+        } else {
+            let pages = comments.len().div_ceil(COMMENTS_PER_PAGE).max(1);
+            bot.edit_message_text(
+                msg.chat.id,
+                replied.id,
+                get_discussion_message(&proposal, &comments, pages - 1),
+            )
             .parse_mode(ParseMode::MarkdownV2)
-            .reply_markup(new_keyboard)
+            .reply_markup(discussion_keyboard(proposal_id, pages - 1, pages))
             .await?;
-        dialogue.exit().await?;
-
-        let _ = delete_up_to_messages(&bot, msg.chat.id.0, msg.id.0, msg_id.0).await?;
+        }
     } else {
-        log::warn!("message not found");
+        log::warn!("proposal {} not found", proposal_id);
     }
     Ok(())
 }
 
-pub async fn receive_expiration_date_handler(
+/// Vetoes the proposal with the reason the admin typed, then announces it. Only the admin
+/// who clicked Veto gets here, see `find_pending_veto`
+pub async fn receive_veto_reason_handler(
     bot: Bot,
-    dialogue: ProposalPromptDialogue,
+    veto: PendingVeto,
     msg: Message,
 ) -> Result<(), TgError> {
-    let text = match msg.text() {
-        Some(t) => t,
+    let Some(user) = msg.from() else {
+        return Ok(());
+    };
+    let reason = match msg.text().map(str::trim) {
+        Some(t) if !t.is_empty() => t,
         _ => {
-            bot.send_message(msg.chat.id, "Send me plain text.").await?;
+            bot.send_message(msg.chat.id, "A veto needs a written reason.")
+                .in_topic(topic(&msg))
+                .await?;
             return Ok(());
         }
     };
-
-    if let Some(menu) = GLOBAL_CREATE_PROPOSAL_STORAGE.get(BOT_NAME.to_string()) {
-        let extract_text = |tg_message: &TgMessage| -> Option<String> {
-            if let MessageKind::Common(common) = &tg_message.message.kind {
-                if let MediaKind::Text(media_text) = &common.media_kind {
-                    return Some(media_text.text.clone());
-                }
-            }
-            None
-        };
-
-        let extracted_text = extract_text(&menu).unwrap();
-        let proposal_msg = parse_message(extracted_text.as_str(), None, None, None, Some(text));
-        let menu_msg = menu.message;
-        let msg_id = menu.message_id;
-        let keyboard = find_keyboard_from_message(&menu_msg)?;
-        let mut new_keyboard = keyboard.clone();
-
-        let new_button_text = add_emoji(EXPIRATION_DATE);
-
-        if let Some(button) = new_keyboard
-            .inline_keyboard
-            .get_mut(4)
-            .and_then(|row| row.get_mut(0))
+    GLOBAL_VETO_STORAGE.remove(msg.chat.id, user.id);
+    // the reason is in, the prompt no longer takes a cancel
+    let _ = bot
+        .edit_message_reply_markup(msg.chat.id, veto.prompt)
+        .await;
+
+    match GLOBAL_PROPOSAL_STORAGE.get_by_id(veto.proposal_id) {
+        // the proposal may have been closed or vetoed while the admin was typing
+        Some(proposal)
+            if !matches!(
+                proposal.status,
+                ProposalStatus::Active | ProposalStatus::Passed
+            ) =>
         {
-            button.text = new_button_text.to_string();
-            button.kind = InlineKeyboardButtonKind::CallbackData(new_button_text.to_string());
-        }
-
-        // Edit the message with the new keyboard
-        bot.edit_message_text(msg.chat.id, msg_id, proposal_msg)
-            .parse_mode(ParseMode::MarkdownV2)
-            .reply_markup(new_keyboard)
+            bot.send_message(
+                msg.chat.id,
+                "Only active or passed proposals can be vetoed, this one is no longer either",
+            )
+            .in_topic(topic(&msg))
             .await?;
-        dialogue.exit().await?;
-
-        let _ = delete_up_to_messages(&bot, msg.chat.id.0, msg.id.0, msg_id.0).await?;
-    } else {
-        log::warn!("message not found");
+        }
+        Some(proposal) => veto_proposal(&bot, &msg, user, veto.card, proposal, reason).await?,
+        None => log::warn!("proposal {} not found", veto.proposal_id),
     }
     Ok(())
 }
+
+/// Vetoes the proposal, updates its card and announces the veto
+async fn veto_proposal(
+    bot: &Bot,
+    msg: &Message,
+    admin: &User,
+    card: MessageId,
+    proposal: Proposal,
+    reason: &str,
+) -> Result<(), TgError> {
+    let admin_name = user_name(admin);
+    if proposal.status == ProposalStatus::Active {
+        stop_proposal_poll(bot, &proposal).await;
+    }
+    // the scheduler may have closed it in the meantime, passed proposals can still be vetoed
+    let vetoed = GLOBAL_PROPOSAL_STORAGE.modify(proposal.id, |proposal| {
+        if !matches!(
+            proposal.status,
+            ProposalStatus::Active | ProposalStatus::Passed
+        ) {
+            return None;
+        }
+        proposal.veto(&admin_name, reason);
+        Some(proposal.clone())
+    });
+    let Some(mut proposal) = vetoed.flatten() else {
+        return Ok(());
+    };
+
+    edit_proposal_card(bot, msg.chat.id, card, &proposal).await?;
+    let announcement = format!(
+        "⛔ Proposal #{} \"{}\" was vetoed by {}\nReason: {}",
+        proposal.id, proposal.title, admin_name, reason
+    );
+    announce_result(bot, &mut proposal, announcement).await
+}
@@ -0,0 +1,98 @@
+use crate::dates::chat_timezone;
+use crate::keyboards::see_proposals_keyboard::inline_proposal_keyboard;
+use crate::messages::get_proposal_message;
+use crate::search::{search_proposals, SearchQuery};
+use crate::storage::{Proposal, TgCommentStorage, TgProposalStorage};
+use crate::storage::{GLOBAL_COMMENT_STORAGE, GLOBAL_PROPOSAL_STORAGE};
+use crate::utils::in_member_chats;
+use crate::TgError;
+use chrono::Utc;
+use teloxide::payloads::{AnswerInlineQuerySetters, EditMessageTextInlineSetters};
+use teloxide::prelude::Requester;
+use teloxide::types::{
+    ChatId, InlineQuery, InlineQueryResult, InlineQueryResultArticle, InputMessageContent,
+    InputMessageContentText, ParseMode, UserId,
+};
+use teloxide::Bot;
+
+/// Telegram's limit on the number of results of an inline query
+const MAX_INLINE_RESULTS: usize = 50;
+
+/// Proposals matching the query from the chats the user is in, best match first. "#12"
+/// finds proposal 12 and an empty query lists the newest proposals
+async fn inline_results(bot: &Bot, user_id: UserId, query: &str) -> Vec<Proposal> {
+    let by_id = query
+        .strip_prefix('#')
+        .and_then(|id| id.parse::<u64>().ok())
+        .and_then(|id| GLOBAL_PROPOSAL_STORAGE.get_by_id(id));
+    let found = match (by_id, query.is_empty()) {
+        (Some(proposal), _) => vec![proposal],
+        (None, true) => search_proposals(&SearchQuery::default(), |_| true),
+        (None, false) => {
+            let timezone = chat_timezone(ChatId(user_id.0 as i64));
+            match SearchQuery::parse(query, Utc::now(), timezone) {
+                Ok(parsed) => search_proposals(&parsed, |_| true),
+                Err(_) => vec![],
+            }
+        }
+    };
+    let mut visible = in_member_chats(bot, user_id, found).await;
+    visible.truncate(MAX_INLINE_RESULTS);
+    visible
+}
+
+/// Offers the proposals matching "@bot <query>" as cards that can be sent to any chat
+pub async fn inline_query_handler(bot: Bot, q: InlineQuery) -> Result<(), TgError> {
+    let results = inline_results(&bot, q.from.id, q.query.trim())
+        .await
+        .into_iter()
+        .map(|proposal| {
+            let card = get_proposal_message(
+                &proposal,
+                GLOBAL_COMMENT_STORAGE.count(proposal.id),
+                chat_timezone(proposal.chat_id),
+            );
+            let content = InputMessageContent::Text(
+                InputMessageContentText::new(card).parse_mode(ParseMode::MarkdownV2),
+            );
+            let mut article = InlineQueryResultArticle::new(
+                proposal.id.to_string(),
+                format!("#{} {}", proposal.id, proposal.title),
+                content,
+            )
+            .description(format!("{} · by {}", proposal.status, proposal.author));
+            if let Some(keyboard) = inline_proposal_keyboard(&proposal) {
+                article = article.reply_markup(keyboard);
+            }
+            InlineQueryResult::Article(article)
+        })
+        .collect::<Vec<_>>();
+
+    // results depend on the chats of the user and on live tallies
+    bot.answer_inline_query(q.id, results)
+        .is_personal(true)
+        .cache_time(0)
+        .await?;
+    Ok(())
+}
+
+/// Re-renders a proposal card that was shared through inline mode
+pub async fn edit_inline_proposal_card(
+    bot: &Bot,
+    inline_message_id: &str,
+    proposal: &Proposal,
+) -> Result<(), TgError> {
+    let msg = get_proposal_message(
+        proposal,
+        GLOBAL_COMMENT_STORAGE.count(proposal.id),
+        chat_timezone(proposal.chat_id),
+    );
+    let request = bot
+        .edit_message_text_inline(inline_message_id, msg)
+        .parse_mode(ParseMode::MarkdownV2);
+    match inline_proposal_keyboard(proposal) {
+        Some(keyboard) => request.reply_markup(keyboard).await?,
+        None => request.await?,
+    };
+    Ok(())
+}
@@ -1,20 +1,51 @@
 pub mod callback_handlers;
+pub mod chat_member_handlers;
 pub mod dialogue_handlers;
+pub mod inline_handlers;
+pub mod poll_handlers;
 
-use crate::consts::SUBMIT_A_PROPOSAL;
+use crate::consts::TOPIC_ICON_COLOR;
+use crate::consts::{CALENDAR_CANCEL, SUBMIT_A_PROPOSAL, TEMPLATE};
+use crate::dates::chat_timezone;
+use crate::handler::poll_handlers::open_proposal_poll;
+use crate::keyboards::see_proposals_keyboard::{
+    linked_proposal_keyboard, mirrored_proposal_keyboard, new_see_proporsal_keyboard,
+};
+use crate::messages::get_proposal_message;
+use crate::storage::{
+    Draft, PendingVeto, PinMode, Proposal, ProposalStatus, TgChatSettingsStorage, TgCommentStorage,
+    TgDraftStorage, TgProposalStorage, TgVetoStorage, GLOBAL_CHAT_SETTINGS_STORAGE,
+    GLOBAL_COMMENT_STORAGE, GLOBAL_DRAFT_STORAGE, GLOBAL_PROPOSAL_STORAGE, GLOBAL_VETO_STORAGE,
+};
+use crate::utils::{is_chat_member, is_forum, InTopic};
 use crate::TgError;
-use teloxide::types::{CallbackQuery, InlineKeyboardMarkup, Message};
+use chrono::{DateTime, Utc};
+use lazy_static::lazy_static;
+use regex::Regex;
+use teloxide::types::{CallbackQuery, Message, ParseMode};
+use teloxide::utils::markdown::escape;
 use teloxide::{
+    payloads::{
+        EditMessageTextSetters, PinChatMessageSetters, SendMessageSetters, UnpinChatMessageSetters,
+    },
     prelude::Requester,
-    types::{ChatId, MessageId},
+    types::{ChatId, MessageId, UserId},
     Bot,
 };
 use tokio::time::{sleep, Duration};
 
+lazy_static! {
+    /// The header of a proposal card or of a discussion thread, holding the proposal number.
+    /// Other bot messages mention proposal numbers too, only these take comments
+    static ref PROPOSAL_ID: Regex =
+        Regex::new(r"^(?:Proposal #(\d+)\nStatus: |💬 Discussion of Proposal #(\d+): )").unwrap();
+}
+
 #[derive(Debug)]
 pub enum SubMenuType {
     CreateNewProposal,
     SeeProposals,
+    Calendar,
 }
 
 pub fn match_sub_menu(q: &CallbackQuery) -> Option<SubMenuType> {
@@ -24,10 +55,12 @@ pub fn match_sub_menu(q: &CallbackQuery) -> Option<SubMenuType> {
         .and_then(|msg| msg.reply_markup())
         .and_then(|keyboard| keyboard.inline_keyboard.last())
         .and_then(|last_vec| last_vec.last())
-        .and_then(|last_button| match last_button.text.as_str() {
-            // If the last button is not CREATE_A_PROPOSAL then it's SEE_ALL_PROPOSALS
-            SUBMIT_A_PROPOSAL => Some(SubMenuType::CreateNewProposal),
-            _ => Some(SubMenuType::SeeProposals),
+        .map(|last_button| match last_button.text.as_str() {
+            // If the last button is not CREATE_A_PROPOSAL or a template then it's SEE_ALL_PROPOSALS
+            SUBMIT_A_PROPOSAL => SubMenuType::CreateNewProposal,
+            text if text.starts_with(TEMPLATE) => SubMenuType::CreateNewProposal,
+            CALENDAR_CANCEL => SubMenuType::Calendar,
+            _ => SubMenuType::SeeProposals,
         })
         .ok_or_else(|| {
             anyhow::anyhow!("find_sub_menu_type_from_callback: No valid sub menu found")
@@ -35,9 +68,41 @@ pub fn match_sub_menu(q: &CallbackQuery) -> Option<SubMenuType> {
     res.ok()
 }
 
-pub fn find_keyboard_from_message(msg: &Message) -> anyhow::Result<&InlineKeyboardMarkup> {
-    msg.reply_markup()
-        .ok_or_else(|| anyhow::anyhow!("find_keyboard_from_message: No valid sub menu found"))
+/// The clicking member's current draft, when the clicked message is that draft
+pub fn find_draft(q: &CallbackQuery) -> Option<Draft> {
+    let message = q.message.as_ref()?;
+    GLOBAL_DRAFT_STORAGE
+        .get(q.from.id)
+        .filter(|draft| draft.message_id == Some(message.id))
+}
+
+/// Finds the proposal a bot message (a proposal card or a discussion thread) refers to
+pub fn find_proposal_id_from_message(msg: &Message) -> Option<u64> {
+    if !msg.from().is_some_and(|user| user.is_bot) {
+        return None;
+    }
+    msg.text()
+        .and_then(|text| PROPOSAL_ID.captures(text))
+        .and_then(|caps| caps.get(1).or_else(|| caps.get(2)))
+        .and_then(|id| id.as_str().parse::<u64>().ok())
+}
+
+/// Filters messages that reply to a proposal card or discussion thread of a proposal of the
+/// same chat. Cards shared to or opened in other chats don't take their replies
+pub fn find_replied_proposal_id(msg: Message) -> Option<u64> {
+    let proposal_id = msg
+        .reply_to_message()
+        .and_then(find_proposal_id_from_message)?;
+    GLOBAL_PROPOSAL_STORAGE
+        .get_by_id(proposal_id)
+        .filter(|proposal| proposal.chat_id == msg.chat.id)
+        .map(|proposal| proposal.id)
+}
+
+/// Filters messages from an admin whose veto waits for its reason in this chat
+pub fn find_pending_veto(msg: Message) -> Option<PendingVeto> {
+    let admin = msg.from()?.id;
+    GLOBAL_VETO_STORAGE.get(msg.chat.id, admin)
 }
 
 pub async fn delete_up_to_messages(
@@ -55,3 +120,247 @@ pub async fn delete_up_to_messages(
     }
     Ok(())
 }
+
+/// Re-renders a proposal card in place after the proposal changed
+pub async fn edit_proposal_card(
+    bot: &Bot,
+    chat_id: ChatId,
+    message_id: MessageId,
+    proposal: &Proposal,
+) -> Result<(), TgError> {
+    let msg = get_proposal_message(
+        proposal,
+        GLOBAL_COMMENT_STORAGE.count(proposal.id),
+        chat_timezone(chat_id),
+    );
+    // cards opened from a link don't carry the chat's admin actions
+    let keyboard = match chat_id == proposal.chat_id {
+        true => new_see_proporsal_keyboard(proposal)?,
+        false => linked_proposal_keyboard(proposal),
+    };
+    bot.edit_message_text(chat_id, message_id, msg)
+        .parse_mode(ParseMode::MarkdownV2)
+        .reply_markup(keyboard)
+        .await?;
+    Ok(())
+}
+
+/// Opens the proposal of a t.me/<bot>?start=p_<id> link in the member's private chat.
+/// Proposals are only shown to members of the chat they belong to
+pub async fn send_linked_proposal(
+    bot: &Bot,
+    chat_id: ChatId,
+    user_id: UserId,
+    proposal_id: u64,
+) -> Result<(), TgError> {
+    let proposal = match GLOBAL_PROPOSAL_STORAGE.get_by_id(proposal_id) {
+        Some(proposal) if is_chat_member(bot, proposal.chat_id, user_id).await => proposal,
+        _ => {
+            bot.send_message(
+                chat_id,
+                format!(
+                    "Proposal #{} doesn't exist or belongs to a chat you're not in",
+                    proposal_id
+                ),
+            )
+            .await?;
+            return Ok(());
+        }
+    };
+    let msg = get_proposal_message(
+        &proposal,
+        GLOBAL_COMMENT_STORAGE.count(proposal.id),
+        chat_timezone(chat_id),
+    );
+    bot.send_message(chat_id, msg)
+        .parse_mode(ParseMode::MarkdownV2)
+        .reply_markup(linked_proposal_keyboard(&proposal))
+        .await?;
+    Ok(())
+}
+
+/// Telegram's limit on the length of a forum topic name
+const TOPIC_NAME_LENGTH: usize = 128;
+
+/// Posts a new proposal's card to its chat, in a topic of its own when the chat has topics
+/// enabled, then opens its poll if it is voted on in one and voting started. Stores what it
+/// changes, the proposal has to be stored already
+pub async fn publish_proposal(bot: &Bot, proposal: &mut Proposal) -> Result<(), TgError> {
+    if is_forum(bot, proposal.chat_id).await {
+        let name = format!("#{} {}", proposal.id, proposal.title)
+            .chars()
+            .take(TOPIC_NAME_LENGTH)
+            .collect::<String>();
+        // an empty emoji id keeps the default icon
+        match bot
+            .create_forum_topic(proposal.chat_id, name, TOPIC_ICON_COLOR, "")
+            .await
+        {
+            Ok(topic) => {
+                proposal.topic = Some(topic.message_thread_id);
+                GLOBAL_PROPOSAL_STORAGE.modify(proposal.id, |stored| stored.topic = proposal.topic);
+            }
+            Err(err) => log::warn!(
+                "could not create a topic for proposal {}: {}",
+                proposal.id,
+                err
+            ),
+        }
+    }
+
+    let card = bot
+        .send_message(
+            proposal.chat_id,
+            get_proposal_message(proposal, 0, chat_timezone(proposal.chat_id)),
+        )
+        .parse_mode(ParseMode::MarkdownV2)
+        .reply_markup(new_see_proporsal_keyboard(proposal)?)
+        .in_topic(proposal.topic)
+        .await?;
+    proposal.card = Some(card.id);
+    GLOBAL_PROPOSAL_STORAGE.modify(proposal.id, |stored| stored.card = proposal.card);
+    pin_proposal_card(bot, proposal).await;
+    mirror_to_channel(bot, proposal, None).await;
+    open_proposal_poll(bot, proposal).await
+}
+
+/// Cross-posts the proposal as a read-only card to the chat's linked channel, if it has one,
+/// under an optional headline such as the result of the vote
+pub async fn mirror_to_channel(bot: &Bot, proposal: &Proposal, headline: Option<&str>) {
+    let Some(channel) = GLOBAL_CHAT_SETTINGS_STORAGE.get(proposal.chat_id).channel else {
+        return;
+    };
+    let card = get_proposal_message(
+        proposal,
+        GLOBAL_COMMENT_STORAGE.count(proposal.id),
+        chat_timezone(proposal.chat_id),
+    );
+    let msg = match headline {
+        Some(headline) => format!("{}\n\n{}", escape(headline), card),
+        None => card,
+    };
+    let sent = match mirrored_proposal_keyboard(proposal) {
+        Ok(keyboard) => {
+            bot.send_message(channel, msg)
+                .parse_mode(ParseMode::MarkdownV2)
+                .reply_markup(keyboard)
+                .await
+        }
+        Err(err) => {
+            log::warn!(
+                "could not build the link to proposal {}: {}",
+                proposal.id,
+                err
+            );
+            return;
+        }
+    };
+    if let Err(err) = sent {
+        log::warn!(
+            "could not mirror proposal {} to channel {}: {}",
+            proposal.id,
+            channel,
+            err
+        );
+    }
+}
+
+/// Pins the card of an active proposal when the chat pins them, and stores that it did
+pub async fn pin_proposal_card(bot: &Bot, proposal: &mut Proposal) {
+    let (ProposalStatus::Active, Some(card)) = (proposal.status, proposal.card) else {
+        return;
+    };
+    let silent = match GLOBAL_CHAT_SETTINGS_STORAGE.get(proposal.chat_id).pin_mode {
+        PinMode::Off => return,
+        PinMode::On => false,
+        PinMode::Silent => true,
+    };
+    match bot
+        .pin_chat_message(proposal.chat_id, card)
+        .disable_notification(silent)
+        .await
+    {
+        Ok(_) => {
+            proposal.card_pinned = true;
+            GLOBAL_PROPOSAL_STORAGE.modify(proposal.id, |stored| stored.card_pinned = true);
+        }
+        Err(err) => log::warn!("could not pin proposal {}: {}", proposal.id, err),
+    }
+}
+
+/// Unpins a message the bot pinned earlier
+async fn unpin(bot: &Bot, chat_id: ChatId, message_id: MessageId) {
+    if let Err(err) = bot.unpin_chat_message(chat_id).message_id(message_id).await {
+        log::warn!("could not unpin message {}: {}", message_id, err);
+    }
+}
+
+/// Announces that voting on the proposal ended in its topic, swaps its pinned card for the
+/// pinned announcement and closes the topic. Stores what it changes
+pub async fn announce_result(
+    bot: &Bot,
+    proposal: &mut Proposal,
+    announcement: String,
+) -> Result<(), TgError> {
+    if let (true, Some(card)) = (proposal.card_pinned, proposal.card) {
+        unpin(bot, proposal.chat_id, card).await;
+        proposal.card_pinned = false;
+    }
+    if let Some((results, _)) = proposal.pinned_results.take() {
+        unpin(bot, proposal.chat_id, results).await;
+    }
+    GLOBAL_PROPOSAL_STORAGE.modify(proposal.id, |stored| {
+        stored.card_pinned = false;
+        stored.pinned_results = None;
+    });
+
+    let sent = bot
+        .send_message(proposal.chat_id, &announcement)
+        .in_topic(proposal.topic)
+        .await?;
+    mirror_to_channel(bot, proposal, Some(&announcement)).await;
+    let hours = GLOBAL_CHAT_SETTINGS_STORAGE
+        .get(proposal.chat_id)
+        .pin_results_hours;
+    if hours > 0 {
+        match bot
+            .pin_chat_message(proposal.chat_id, sent.id)
+            .disable_notification(true)
+            .await
+        {
+            Ok(_) => {
+                proposal.pinned_results =
+                    Some((sent.id, Utc::now() + chrono::Duration::hours(hours.into())));
+                GLOBAL_PROPOSAL_STORAGE.modify(proposal.id, |stored| {
+                    stored.pinned_results = proposal.pinned_results
+                });
+            }
+            Err(err) => log::warn!("could not pin the results of {}: {}", proposal.id, err),
+        }
+    }
+    close_proposal_topic(bot, proposal).await;
+    Ok(())
+}
+
+/// Unpins results whose pinning period is over and forgets them in the stored proposal
+pub async fn unpin_expired_results(bot: &Bot, proposal: &Proposal, now: DateTime<Utc>) {
+    if let Some((results, until)) = proposal.pinned_results {
+        if until <= now {
+            unpin(bot, proposal.chat_id, results).await;
+            GLOBAL_PROPOSAL_STORAGE.clear_pinned_results(proposal.id, results);
+        }
+    }
+}
+
+/// Closes the proposal's forum topic once voting on it has ended
+async fn close_proposal_topic(bot: &Bot, proposal: &Proposal) {
+    if let Some(topic) = proposal.topic {
+        if let Err(err) = bot.close_forum_topic(proposal.chat_id, topic).await {
+            log::warn!(
+                "could not close the topic of proposal {}: {}",
+                proposal.id,
+                err
+            );
+        }
+    }
+}
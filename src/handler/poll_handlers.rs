@@ -0,0 +1,122 @@
+use crate::consts::{POLL_AGAINST, POLL_FOR};
+use crate::storage::GLOBAL_PROPOSAL_STORAGE;
+use crate::storage::{Ballot, Proposal, ProposalPoll, TgProposalStorage};
+use crate::utils::{check_eligibility, InTopic};
+use crate::TgError;
+use chrono::Utc;
+use teloxide::payloads::SendPollSetters;
+use teloxide::prelude::Requester;
+use teloxide::types::{Poll, PollAnswer};
+use teloxide::Bot;
+
+/// Telegram's limit on the length of a poll question
+const POLL_QUESTION_LENGTH: usize = 300;
+
+/// Posts the poll of a `Ballot::Poll` proposal open for voting, once, and stores it. The
+/// scheduler posts it at the starting date when that is later. Telegram only closes polls
+/// on its own up to 10 minutes ahead, so the scheduler stops them at the expiration date
+/// instead
+pub(crate) async fn open_proposal_poll(bot: &Bot, proposal: &mut Proposal) -> Result<(), TgError> {
+    let Ballot::Poll { anonymous } = proposal.ballot else {
+        return Ok(());
+    };
+    if proposal.voting_refusal(Utc::now()).is_some() || proposal.poll.is_some() {
+        return Ok(());
+    }
+    let question = format!("Proposal #{}: {}", proposal.id, proposal.title)
+        .chars()
+        .take(POLL_QUESTION_LENGTH)
+        .collect::<String>();
+    let message = bot
+        .send_poll(
+            proposal.chat_id,
+            question,
+            [POLL_FOR.to_string(), POLL_AGAINST.to_string()],
+        )
+        .is_anonymous(anonymous)
+        .in_topic(proposal.topic)
+        .await?;
+    if let Some(poll) = message.poll() {
+        proposal.poll = Some(ProposalPoll {
+            poll_id: poll.id.clone(),
+            message_id: message.id,
+        });
+        GLOBAL_PROPOSAL_STORAGE.modify(proposal.id, |stored| stored.poll = proposal.poll.clone());
+    }
+    Ok(())
+}
+
+/// Stops the proposal's poll, if it has one, returning its final state
+pub(crate) async fn stop_proposal_poll(bot: &Bot, proposal: &Proposal) -> Option<Poll> {
+    let poll = proposal.poll.as_ref()?;
+    match bot.stop_poll(proposal.chat_id, poll.message_id).await {
+        Ok(poll) => Some(poll),
+        Err(err) => {
+            log::warn!(
+                "could not stop the poll of proposal {}: {}",
+                proposal.id,
+                err
+            );
+            None
+        }
+    }
+}
+
+/// Copies the tally of an anonymous poll into the proposal. Non-anonymous polls are counted
+/// answer by answer instead, so that votes of ineligible members are left out
+pub(crate) fn sync_poll_tally(proposal: &mut Proposal, poll: &Poll) {
+    if proposal.ballot != (Ballot::Poll { anonymous: true }) {
+        return;
+    }
+    let count = |option: usize| {
+        poll.options
+            .get(option)
+            .map_or(0, |option| option.voter_count.max(0) as u64)
+    };
+    proposal.vote = count(0);
+    proposal.votes_against = count(1);
+}
+
+/// Records who voted which way in a non-anonymous proposal poll. Polls keep working when
+/// forwarded, so answers of members who may not vote are not counted
+pub async fn poll_answer_handler(bot: Bot, answer: PollAnswer) -> Result<(), TgError> {
+    let Some(proposal) = GLOBAL_PROPOSAL_STORAGE.get_by_poll_id(&answer.poll_id) else {
+        return Ok(());
+    };
+    if proposal.voting_refusal(Utc::now()).is_some() {
+        return Ok(());
+    }
+    let option = answer.option_ids.first().copied();
+    if option.is_some()
+        && check_eligibility(&bot, proposal.chat_id, answer.user.id, "vote")
+            .await
+            .is_err()
+    {
+        return Ok(());
+    }
+    // answers come in concurrently, and the proposal may have closed during the check
+    GLOBAL_PROPOSAL_STORAGE.modify(proposal.id, |proposal| {
+        if proposal.voting_refusal(Utc::now()).is_some() {
+            return;
+        }
+        match option {
+            None => proposal.retract_vote(answer.user.id),
+            Some(0) => proposal.vote(answer.user.id, true),
+            Some(_) => proposal.vote(answer.user.id, false),
+        };
+    });
+    Ok(())
+}
+
+/// Keeps the tally of an anonymous poll proposal in step with its poll, until voting ends
+pub async fn poll_handler(poll: Poll) -> Result<(), TgError> {
+    let Some(proposal) = GLOBAL_PROPOSAL_STORAGE.get_by_poll_id(&poll.id) else {
+        return Ok(());
+    };
+    GLOBAL_PROPOSAL_STORAGE.modify(proposal.id, |proposal| {
+        if proposal.voting_refusal(Utc::now()).is_none() {
+            sync_poll_tally(proposal, &poll);
+        }
+    });
+    Ok(())
+}
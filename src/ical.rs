@@ -0,0 +1,81 @@
+use crate::consts::BOT_NAME;
+use crate::storage::Proposal;
+use chrono::{DateTime, Utc};
+
+const ICAL_DATE_FORMAT: &str = "%Y%m%dT%H%M%SZ";
+
+/// Escapes text values as RFC 5545 requires
+fn escape_text(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace('\n', "\\n")
+}
+
+/// Folds a content line so no line is longer than 75 octets
+fn fold_line(line: &str) -> String {
+    let mut folded = String::new();
+    let mut length = 0;
+    for c in line.chars() {
+        if length + c.len_utf8() > 75 {
+            folded.push_str("\r\n ");
+            length = 1;
+        }
+        folded.push(c);
+        length += c.len_utf8();
+    }
+    folded
+}
+
+/// The voting window of a proposal as a calendar event, `None` when it has no expiration date
+fn proposal_event(proposal: &Proposal, now: DateTime<Utc>) -> Option<Vec<String>> {
+    let end = proposal.expiration_date?;
+    let start = proposal.starting_date.unwrap_or(now).min(end);
+    let mut description = format!("Proposal #{} ({})", proposal.id, proposal.status);
+    if !proposal.description.is_empty() {
+        description.push_str(&format!("\n{}", proposal.description));
+    }
+
+    Some(vec![
+        "BEGIN:VEVENT".to_string(),
+        format!(
+            "UID:proposal-{}-{}@{}",
+            proposal.id, proposal.chat_id, BOT_NAME
+        ),
+        format!("DTSTAMP:{}", now.format(ICAL_DATE_FORMAT)),
+        format!("DTSTART:{}", start.format(ICAL_DATE_FORMAT)),
+        format!("DTEND:{}", end.format(ICAL_DATE_FORMAT)),
+        format!(
+            "SUMMARY:{}",
+            escape_text(&format!("Vote: {}", proposal.title))
+        ),
+        format!("DESCRIPTION:{}", escape_text(&description)),
+        "END:VEVENT".to_string(),
+    ])
+}
+
+/// Renders the voting windows of the proposals as an iCalendar (.ics) file
+pub(crate) fn proposals_calendar<'a>(
+    proposals: impl IntoIterator<Item = &'a Proposal>,
+    now: DateTime<Utc>,
+) -> String {
+    let mut lines = vec![
+        "BEGIN:VCALENDAR".to_string(),
+        "VERSION:2.0".to_string(),
+        format!("PRODID:-//{}//Proposals//EN", BOT_NAME),
+        "CALSCALE:GREGORIAN".to_string(),
+        "METHOD:PUBLISH".to_string(),
+    ];
+    lines.extend(
+        proposals
+            .into_iter()
+            .filter_map(|proposal| proposal_event(proposal, now))
+            .flatten(),
+    );
+    lines.push("END:VCALENDAR".to_string());
+
+    lines
+        .iter()
+        .map(|line| format!("{}\r\n", fold_line(line)))
+        .collect()
+}
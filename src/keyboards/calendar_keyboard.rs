@@ -0,0 +1,173 @@
+use crate::consts::{
+    CALENDAR_CANCEL, CALENDAR_DAY, CALENDAR_HOUR, CALENDAR_IGNORE, CALENDAR_MINUTE, CALENDAR_MONTH,
+    NEXT_PAGE, PREVIOUS_PAGE,
+};
+use crate::keyboards::{callback_data, parse_callback_data};
+use chrono::{Datelike, Months, NaiveDate};
+use teloxide::types::{InlineKeyboardButton, InlineKeyboardMarkup};
+
+const WEEKDAY_NAMES: [&str; 7] = ["Mo", "Tu", "We", "Th", "Fr", "Sa", "Su"];
+
+/// The draft field a picker fills in, see `CreateNewProposalKeyboard::Field`
+#[derive(Debug, Clone, Copy)]
+pub struct PickerField {
+    pub template: usize,
+    pub field: usize,
+}
+
+#[derive(Debug, Clone)]
+pub enum CalendarKeyboard {
+    /// Show the month grid of the given month
+    Month(PickerField, NaiveDate),
+    /// A day was picked, show the hour picker
+    Day(PickerField, NaiveDate),
+    /// An hour was picked, show the minute picker
+    Hour(PickerField, NaiveDate, u32),
+    /// The minute was picked, the date is complete
+    Minute(PickerField, NaiveDate, u32, u32),
+    Cancel,
+    Ignore,
+}
+
+impl CalendarKeyboard {
+    pub fn new(text: &str) -> Self {
+        let (action, args) = parse_callback_data(text);
+        let date = |year: u64, month: u64, day: u64| {
+            NaiveDate::from_ymd_opt(year as i32, month as u32, day as u32)
+        };
+        let picker = |args: &[u64]| PickerField {
+            template: args[0] as usize,
+            field: args[1] as usize,
+        };
+        let parsed = match (action, args.as_slice()) {
+            (CALENDAR_MONTH, [_, _, year, month]) => {
+                date(*year, *month, 1).map(|date| Self::Month(picker(&args), date))
+            }
+            (CALENDAR_DAY, [_, _, year, month, day]) => {
+                date(*year, *month, *day).map(|date| Self::Day(picker(&args), date))
+            }
+            (CALENDAR_HOUR, [_, _, year, month, day, hour]) => {
+                date(*year, *month, *day).map(|date| Self::Hour(picker(&args), date, *hour as u32))
+            }
+            (CALENDAR_MINUTE, [_, _, year, month, day, hour, minute]) => date(*year, *month, *day)
+                .map(|date| Self::Minute(picker(&args), date, *hour as u32, *minute as u32)),
+            (CALENDAR_CANCEL, _) => Some(Self::Cancel),
+            _ => None,
+        };
+        parsed.unwrap_or(Self::Ignore)
+    }
+}
+
+fn ignored_button(text: &str) -> InlineKeyboardButton {
+    InlineKeyboardButton::callback(text.to_owned(), CALENDAR_IGNORE.to_owned())
+}
+
+fn cancel_row() -> Vec<InlineKeyboardButton> {
+    vec![InlineKeyboardButton::callback(
+        CALENDAR_CANCEL,
+        CALENDAR_CANCEL.to_owned(),
+    )]
+}
+
+fn picker_args(picker: PickerField, date: NaiveDate) -> Vec<u64> {
+    vec![
+        picker.template as u64,
+        picker.field as u64,
+        date.year() as u64,
+        date.month() as u64,
+        date.day() as u64,
+    ]
+}
+
+/// Month grid starting on Monday, with navigation to the previous and next month
+pub fn calendar_keyboard(picker: PickerField, month: NaiveDate) -> InlineKeyboardMarkup {
+    let first = month.with_day(1).unwrap_or(month);
+    let mut keyboard = InlineKeyboardMarkup::default();
+
+    keyboard = keyboard.append_row(vec![ignored_button(&first.format("%B %Y").to_string())]);
+    keyboard = keyboard.append_row(
+        WEEKDAY_NAMES
+            .iter()
+            .map(|name| ignored_button(name))
+            .collect::<Vec<_>>(),
+    );
+
+    let mut week = vec![ignored_button(" "); first.weekday().num_days_from_monday() as usize];
+    let mut day = first;
+    while day.month() == first.month() {
+        week.push(InlineKeyboardButton::callback(
+            day.day().to_string(),
+            callback_data(CALENDAR_DAY, &picker_args(picker, day)),
+        ));
+        if week.len() == 7 {
+            keyboard = keyboard.append_row(std::mem::take(&mut week));
+        }
+        day = match day.succ_opt() {
+            Some(next) => next,
+            None => break,
+        };
+    }
+    if !week.is_empty() {
+        week.resize(7, ignored_button(" "));
+        keyboard = keyboard.append_row(week);
+    }
+
+    let month_args = |date: Option<NaiveDate>| {
+        date.map(|date| {
+            let mut args = picker_args(picker, date);
+            args.truncate(4);
+            callback_data(CALENDAR_MONTH, &args)
+        })
+    };
+    let navigation = [
+        (
+            PREVIOUS_PAGE,
+            month_args(first.checked_sub_months(Months::new(1))),
+        ),
+        (
+            NEXT_PAGE,
+            month_args(first.checked_add_months(Months::new(1))),
+        ),
+    ];
+    keyboard = keyboard.append_row(
+        navigation
+            .into_iter()
+            .filter_map(|(text, data)| Some(InlineKeyboardButton::callback(text, data?)))
+            .collect::<Vec<_>>(),
+    );
+
+    keyboard.append_row(cancel_row())
+}
+
+/// The 24 hours of the picked day
+pub fn hour_keyboard(picker: PickerField, date: NaiveDate) -> InlineKeyboardMarkup {
+    let buttons = (0..24)
+        .map(|hour| {
+            let mut args = picker_args(picker, date);
+            args.push(hour);
+            InlineKeyboardButton::callback(
+                format!("{:02}:00", hour),
+                callback_data(CALENDAR_HOUR, &args),
+            )
+        })
+        .collect::<Vec<_>>();
+
+    InlineKeyboardMarkup::new(buttons.chunks(6).map(|row| row.to_vec())).append_row(cancel_row())
+}
+
+/// Minutes of the picked hour, in steps of five
+pub fn minute_keyboard(picker: PickerField, date: NaiveDate, hour: u32) -> InlineKeyboardMarkup {
+    let buttons = (0..60)
+        .step_by(5)
+        .map(|minute| {
+            let mut args = picker_args(picker, date);
+            args.extend([hour as u64, minute]);
+            InlineKeyboardButton::callback(
+                format!("{:02}:{:02}", hour, minute),
+                callback_data(CALENDAR_MINUTE, &args),
+            )
+        })
+        .collect::<Vec<_>>();
+
+    InlineKeyboardMarkup::new(buttons.chunks(4).map(|row| row.to_vec())).append_row(cancel_row())
+}
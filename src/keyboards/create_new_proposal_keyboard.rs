@@ -1,48 +1,41 @@
-use crate::consts::{
-    CLOSE, DESCRIPTION, EXPIRATION_DATE, MAIN_MENU, STARTING_DATE, SUBMIT_A_PROPOSAL, TITLE,
+use crate::consts::{CLOSE, FIELD, MAIN_MENU, SUBMIT_A_PROPOSAL, TEMPLATE};
+use crate::keyboards::{
+    add_emoji, callback_data, callback_text_data, parse_callback_data, parse_callback_text,
 };
-use crate::keyboards::add_emoji;
+use crate::templates::ProposalTemplate;
 use teloxide::types::{InlineKeyboardButton, InlineKeyboardMarkup};
 
+/// Buttons of the proposal draft. The template picker sends the template name, the draft
+/// buttons its index in `chat_templates` and the field index in the template, checked
+/// against the draft by `draft_template`
 #[derive(Debug, Clone)]
-pub enum CreateNewProposalKeyboard<'a> {
+pub enum CreateNewProposalKeyboard {
     MainMenu,
     Close,
-    Title(&'a str),
-    Description(&'a str),
-    StartingDate(&'a str),
-    ExpirationDate(&'a str),
-    SubmitProposal(&'a str),
+    Template(String),
+    Field(usize, usize),
+    SubmitProposal(usize),
 }
 
-impl<'a> CreateNewProposalKeyboard<'a> {
-    pub(crate) fn new(text: &'a str) -> Self {
-        match text {
-            t if t == MAIN_MENU || t == add_emoji(MAIN_MENU).as_str() => Self::MainMenu,
-            t if t == CLOSE || t == add_emoji(CLOSE).as_str() => Self::Close,
-            t if t == TITLE || t == add_emoji(TITLE).as_str() => Self::Title(text),
-            t if t == DESCRIPTION || t == add_emoji(DESCRIPTION).as_str() => {
-                Self::Description(text)
-            }
-            t if t == STARTING_DATE || t == add_emoji(STARTING_DATE).as_str() => {
-                Self::StartingDate(text)
-            }
-            t if t == EXPIRATION_DATE || t == add_emoji(EXPIRATION_DATE).as_str() => {
-                Self::ExpirationDate(text)
-            }
-            t if t == SUBMIT_A_PROPOSAL || t == add_emoji(SUBMIT_A_PROPOSAL).as_str() => {
-                Self::SubmitProposal(text)
-            }
+impl CreateNewProposalKeyboard {
+    pub(crate) fn new(text: &str) -> Self {
+        match parse_callback_data(text) {
+            (TEMPLATE, _) => match parse_callback_text(text, 0) {
+                Some((_, Some(name))) => Self::Template(name.to_string()),
+                _ => Self::MainMenu,
+            },
+            (FIELD, args) if args.len() == 2 => Self::Field(args[0] as usize, args[1] as usize),
+            (SUBMIT_A_PROPOSAL, args) if args.len() == 1 => Self::SubmitProposal(args[0] as usize),
+            (CLOSE, _) => Self::Close,
             _ => Self::MainMenu,
         }
     }
 }
 
 fn create_proposal_keyboard(
-    title: bool,
-    description: bool,
-    starting_date: bool,
-    expiration_date: bool,
+    template_index: usize,
+    template: &ProposalTemplate,
+    filled: &[bool],
 ) -> anyhow::Result<InlineKeyboardMarkup> {
     let mut keyboard = InlineKeyboardMarkup::default();
 
@@ -53,54 +46,49 @@ fn create_proposal_keyboard(
         InlineKeyboardButton::callback(add_emoji(CLOSE), CLOSE.to_owned()),
     ]);
 
-    // 2nd row
-    keyboard = keyboard.append_row(vec![match title {
-        true => InlineKeyboardButton::callback(add_emoji(TITLE), add_emoji(TITLE)),
-        false => InlineKeyboardButton::callback(TITLE.to_owned(), TITLE.to_owned()),
-    }]);
-
-    // 3th row
-    keyboard = keyboard.append_row(vec![match description {
-        true => InlineKeyboardButton::callback(add_emoji(DESCRIPTION), add_emoji(DESCRIPTION)),
-        false => InlineKeyboardButton::callback(DESCRIPTION.to_owned(), DESCRIPTION.to_owned()),
-    }]);
-
-    // 4th row
-    keyboard = keyboard.append_row(vec![match starting_date {
-        true => InlineKeyboardButton::callback(add_emoji(STARTING_DATE), add_emoji(STARTING_DATE)),
-        false => InlineKeyboardButton::callback(STARTING_DATE.to_owned(), STARTING_DATE.to_owned()),
-    }]);
-
-    // 5th row
-    keyboard = keyboard.append_row(vec![match expiration_date {
-        true => {
-            InlineKeyboardButton::callback(add_emoji(EXPIRATION_DATE), add_emoji(EXPIRATION_DATE))
-        }
-        false => {
-            InlineKeyboardButton::callback(EXPIRATION_DATE.to_owned(), EXPIRATION_DATE.to_owned())
-        }
-    }]);
+    // one row per template field, ticked once filled in
+    for (field_index, field) in template.fields.iter().enumerate() {
+        let text = match filled.get(field_index) {
+            Some(true) => format!("✅ {}", field.name),
+            _ => field.name.clone(),
+        };
+        keyboard = keyboard.append_row(vec![InlineKeyboardButton::callback(
+            text,
+            callback_data(FIELD, &[template_index as u64, field_index as u64]),
+        )]);
+    }
 
-    // 6th row
-    keyboard = keyboard.append_row(vec![match expiration_date {
-        true => InlineKeyboardButton::callback(
-            add_emoji(SUBMIT_A_PROPOSAL),
-            add_emoji(SUBMIT_A_PROPOSAL),
-        ),
-        false => InlineKeyboardButton::callback(SUBMIT_A_PROPOSAL, SUBMIT_A_PROPOSAL.to_owned()),
-    }]);
+    // last row
+    keyboard = keyboard.append_row(vec![InlineKeyboardButton::callback(
+        SUBMIT_A_PROPOSAL,
+        callback_data(SUBMIT_A_PROPOSAL, &[template_index as u64]),
+    )]);
 
     Ok(keyboard)
 }
 
 pub fn new_proporsal_keyboard(
-    title: bool,
-    description: bool,
-    starting_date: bool,
-    expiration_date: bool,
+    template_index: usize,
+    template: &ProposalTemplate,
+    filled: &[bool],
 ) -> anyhow::Result<InlineKeyboardMarkup> {
-    match create_proposal_keyboard(title, description, starting_date, expiration_date) {
+    match create_proposal_keyboard(template_index, template, filled) {
         Ok(keyboard) => Ok(keyboard),
         _ => Err(anyhow::anyhow!("Error creating keyboard")),
     }
 }
+
+/// Lets the user pick which of the chat's templates to create the proposal from
+pub fn template_picker_keyboard(templates: &[ProposalTemplate]) -> InlineKeyboardMarkup {
+    let buttons = templates
+        .iter()
+        .map(|template| {
+            InlineKeyboardButton::callback(
+                format!("{} {}", TEMPLATE, template.name),
+                callback_text_data(TEMPLATE, &[], Some(&template.name)),
+            )
+        })
+        .collect::<Vec<_>>();
+
+    InlineKeyboardMarkup::new(buttons.chunks(2).map(|row| row.to_vec()))
+}
@@ -1,10 +1,9 @@
+pub mod calendar_keyboard;
 pub mod create_new_proposal_keyboard;
 pub mod see_proposals_keyboard;
 use teloxide::types::{InlineKeyboardButton, InlineKeyboardMarkup};
 
-use crate::consts::{
-    CLOSE, CREATE_A_PROPOSAL, DESCRIPTION, EXPIRATION_DATE, MAIN_MENU, STARTING_DATE, TITLE,
-};
+use crate::consts::{CLOSE, CREATE_A_PROPOSAL, MAIN_MENU};
 
 /// Default layout for the keyboard
 fn create_keyboard(actions: Vec<&str>) -> InlineKeyboardMarkup {
@@ -26,16 +25,50 @@ pub fn add_emoji(text: &str) -> String {
     let button = match text {
         MAIN_MENU => format!("🏠 {}", text),
         CLOSE => format!("❌ {}", text),
-        TITLE => format!("✅ {}", text),
-        DESCRIPTION => format!("✅ {}", text),
-        STARTING_DATE => format!("✅ {}", text),
-        EXPIRATION_DATE => format!("✅{}", text),
         CREATE_A_PROPOSAL => format!("✅{}", text),
         _ => text.to_string(),
     };
     button
 }
 
+/// Builds the callback data for an action that targets a specific item, e.g. "👍:3"
+pub fn callback_data(action: &str, args: &[u64]) -> String {
+    args.iter()
+        .fold(action.to_string(), |data, arg| format!("{}:{}", data, arg))
+}
+
+/// Splits callback data built by `callback_data` back into the action and its arguments
+pub fn parse_callback_data(data: &str) -> (&str, Vec<u64>) {
+    let mut parts = data.split(':');
+    let action = parts.next().unwrap_or_default();
+    let args = parts.filter_map(|arg| arg.parse::<u64>().ok()).collect();
+    (action, args)
+}
+
+/// Builds callback data that ends in free text, e.g. a category name: "📚:0:budget"
+pub fn callback_text_data(action: &str, args: &[u64], text: Option<&str>) -> String {
+    let data = callback_data(action, args);
+    match text {
+        Some(text) => format!("{}:{}", data, text),
+        None => data,
+    }
+}
+
+/// Splits callback data built by `callback_text_data` with `count` arguments back into the
+/// arguments and the text. The text may contain ":" itself
+pub fn parse_callback_text(data: &str, count: usize) -> Option<(Vec<u64>, Option<&str>)> {
+    let mut parts = data.splitn(count + 2, ':').skip(1);
+    let args = parts
+        .by_ref()
+        .take(count)
+        .map(|arg| arg.parse::<u64>().ok())
+        .collect::<Option<Vec<_>>>()?;
+    if args.len() < count {
+        return None;
+    }
+    Some((args, parts.next()))
+}
+
 pub fn menu_keyboard() -> InlineKeyboardMarkup {
     create_keyboard(vec!["See Proposals", "Create a Proposal"])
 }
@@ -1,33 +1,305 @@
-use crate::consts::THUMB_UP;
+use crate::consts::{
+    ADD_TO_CALENDAR, ALL_CATEGORIES, BROWSE, CANCEL_VETO, CLOSE_VOTING, COMMENT, DISCUSS, FILTER,
+    NEXT_PAGE, OPEN, PAGE_INDICATOR, PREVIOUS_PAGE, SEARCH, SELECTED, SHARE, SORT,
+    SORT_ENDING_SOONEST, SORT_MOST_DISCUSSED, SORT_MOST_VOTES, SORT_NEWEST, SPONSOR, THUMB_DOWN,
+    THUMB_UP, VETO, VOTE_IN_CHAT,
+};
+use crate::keyboards::{
+    callback_data, callback_text_data, parse_callback_data, parse_callback_text,
+};
+use crate::messages::proposal_link;
+use crate::storage::{Ballot, Proposal, ProposalSort, ProposalStatus};
 use teloxide::types::{InlineKeyboardButton, InlineKeyboardMarkup};
 
 #[derive(Debug, Clone)]
 pub enum SeeProposalsKeyboard {
-    ThumbUp,
+    ThumbUp(u64),
+    ThumbDown(u64),
+    Discuss(u64),
+    Comment(u64),
+    CommentsPage(u64, usize),
+    Sponsor(u64),
+    /// Category to filter by, `None` shows every proposal
+    Filter(Option<String>),
+    /// Page of the proposal browser, with the order and the category it is listed in
+    Browse(usize, ProposalSort, Option<String>),
+    /// Posts the card of a proposal picked in the browser
+    Open(u64),
+    /// Lists the browser in the picked order instead of the one shown, keeping its category
+    Sort(ProposalSort, ProposalSort, Option<String>),
+    /// Page of the search results shown in the clicked message
+    SearchPage(usize),
+    /// The page indicator of a list, which does nothing
+    PageIndicator,
+    CloseVoting(u64),
+    Veto(u64),
+    /// Drops the clicking admin's pending veto of the proposal
+    CancelVeto(u64),
+    AddToCalendar(u64),
+    Share(u64),
+    Unknown,
 }
 
 impl SeeProposalsKeyboard {
     pub fn new(text: &str) -> Self {
-        match text {
-            THUMB_UP => Self::ThumbUp,
-            //THUMB_DOWN => Self::ThumbDown,
-            _ => Self::ThumbUp,
+        // categories are sent by name, they can change while the keyboard is shown
+        let with_category = |count: usize| {
+            parse_callback_text(text, count)
+                .map(|(args, category)| (args, category.map(str::to_string)))
+        };
+        match parse_callback_data(text) {
+            (FILTER, _) => match with_category(0) {
+                Some((_, category)) => Self::Filter(category),
+                None => Self::Unknown,
+            },
+            (BROWSE, _) => match with_category(2) {
+                Some((args, category)) => match sort_at(args[1]) {
+                    Some(sort) => Self::Browse(args[0] as usize, sort, category),
+                    None => Self::Unknown,
+                },
+                None => Self::Unknown,
+            },
+            (SORT, _) => match with_category(2) {
+                Some((args, category)) => match (sort_at(args[0]), sort_at(args[1])) {
+                    (Some(sort), Some(shown)) => Self::Sort(sort, shown, category),
+                    _ => Self::Unknown,
+                },
+                None => Self::Unknown,
+            },
+            (THUMB_UP, args) if args.len() == 1 => Self::ThumbUp(args[0]),
+            (THUMB_DOWN, args) if args.len() == 1 => Self::ThumbDown(args[0]),
+            (DISCUSS, args) if args.len() == 1 => Self::Discuss(args[0]),
+            (COMMENT, args) if args.len() == 1 => Self::Comment(args[0]),
+            (SPONSOR, args) if args.len() == 1 => Self::Sponsor(args[0]),
+            (CLOSE_VOTING, args) if args.len() == 1 => Self::CloseVoting(args[0]),
+            (VETO, args) if args.len() == 1 => Self::Veto(args[0]),
+            (CANCEL_VETO, args) if args.len() == 1 => Self::CancelVeto(args[0]),
+            (ADD_TO_CALENDAR, args) if args.len() == 1 => Self::AddToCalendar(args[0]),
+            (SHARE, args) if args.len() == 1 => Self::Share(args[0]),
+            (OPEN, args) if args.len() == 1 => Self::Open(args[0]),
+            (SEARCH, args) if args.len() == 1 => Self::SearchPage(args[0] as usize),
+            (PAGE_INDICATOR, _) => Self::PageIndicator,
+            (PREVIOUS_PAGE | NEXT_PAGE, args) if args.len() == 2 => {
+                Self::CommentsPage(args[0], args[1] as usize)
+            }
+            _ => Self::Unknown,
         }
     }
 }
 
-fn see_proposal_keyboard() -> anyhow::Result<InlineKeyboardMarkup> {
+/// Buttons to take the proposal elsewhere: the calendar and a link to it
+fn link_row(proposal: &Proposal) -> Vec<InlineKeyboardButton> {
+    let button = |action: &str| {
+        InlineKeyboardButton::callback(action.to_owned(), callback_data(action, &[proposal.id]))
+    };
+    let mut row = vec![];
+    if proposal.expiration_date.is_some() {
+        row.push(button(ADD_TO_CALENDAR));
+    }
+    row.push(button(SHARE));
+    row
+}
+
+fn see_proposal_keyboard(proposal: &Proposal) -> anyhow::Result<InlineKeyboardMarkup> {
+    let button = |action: &str| {
+        InlineKeyboardButton::callback(action.to_owned(), callback_data(action, &[proposal.id]))
+    };
     let mut keyboard = InlineKeyboardMarkup::default();
-    keyboard = keyboard.append_row(vec![
-        InlineKeyboardButton::callback(THUMB_UP, THUMB_UP.to_owned()),
-        //InlineKeyboardButton::callback(THUMB_DOWN, THUMB_DOWN.to_owned()),
-    ]);
+
+    // voting only opens once the proposal has enough sponsors
+    keyboard = keyboard.append_row(match proposal.status {
+        ProposalStatus::SeekingSponsors => vec![button(SPONSOR), button(DISCUSS)],
+        // poll proposals are voted on in the poll posted under the card
+        ProposalStatus::Active if proposal.ballot != Ballot::Buttons => vec![button(DISCUSS)],
+        ProposalStatus::Active => vec![button(THUMB_UP), button(THUMB_DOWN), button(DISCUSS)],
+        _ => vec![button(DISCUSS)],
+    });
+    keyboard = keyboard.append_row(link_row(proposal));
+
+    // admin actions, the handlers refuse anyone who doesn't administer the proposal's chat
+    match proposal.status {
+        ProposalStatus::Active => {
+            keyboard = keyboard.append_row(vec![button(CLOSE_VOTING), button(VETO)]);
+        }
+        ProposalStatus::Passed => {
+            keyboard = keyboard.append_row(vec![button(VETO)]);
+        }
+        _ => {}
+    }
     Ok(keyboard)
 }
 
-pub fn new_see_proporsal_keyboard() -> anyhow::Result<InlineKeyboardMarkup> {
-    match see_proposal_keyboard() {
+/// Keyboard of a proposal card opened from a link in a private chat. The chat's admin
+/// actions and sponsoring stay on the card in the proposal's own chat
+pub fn linked_proposal_keyboard(proposal: &Proposal) -> InlineKeyboardMarkup {
+    let button = |action: &str| {
+        InlineKeyboardButton::callback(action.to_owned(), callback_data(action, &[proposal.id]))
+    };
+    let first_row = match (proposal.status, proposal.ballot) {
+        (ProposalStatus::Active, Ballot::Buttons) => {
+            vec![button(THUMB_UP), button(THUMB_DOWN), button(DISCUSS)]
+        }
+        _ => vec![button(DISCUSS)],
+    };
+    InlineKeyboardMarkup::new([first_row, link_row(proposal)])
+}
+
+/// Keyboard of a read-only card mirrored to a channel: a link back to the vote
+pub fn mirrored_proposal_keyboard(proposal: &Proposal) -> anyhow::Result<InlineKeyboardMarkup> {
+    Ok(InlineKeyboardMarkup::new([vec![
+        InlineKeyboardButton::url(VOTE_IN_CHAT, proposal_link(proposal.id).parse()?),
+    ]]))
+}
+
+/// Keyboard of a proposal card shared through inline mode, only the vote buttons work there
+pub fn inline_proposal_keyboard(proposal: &Proposal) -> Option<InlineKeyboardMarkup> {
+    let button = |action: &str| {
+        InlineKeyboardButton::callback(action.to_owned(), callback_data(action, &[proposal.id]))
+    };
+    match (proposal.status, proposal.ballot) {
+        (ProposalStatus::Active, Ballot::Buttons) => Some(InlineKeyboardMarkup::new([vec![
+            button(THUMB_UP),
+            button(THUMB_DOWN),
+        ]])),
+        _ => None,
+    }
+}
+
+pub fn new_see_proporsal_keyboard(proposal: &Proposal) -> anyhow::Result<InlineKeyboardMarkup> {
+    match see_proposal_keyboard(proposal) {
         Ok(keyboard) => Ok(keyboard),
         _ => Err(anyhow::anyhow!("Error creating keyboard")),
     }
 }
+
+/// Keyboard under a discussion thread: page navigation plus the comment button
+pub fn discussion_keyboard(proposal_id: u64, page: usize, pages: usize) -> InlineKeyboardMarkup {
+    let mut keyboard = InlineKeyboardMarkup::default();
+
+    let mut navigation = vec![];
+    if page > 0 {
+        navigation.push(InlineKeyboardButton::callback(
+            PREVIOUS_PAGE,
+            callback_data(PREVIOUS_PAGE, &[proposal_id, page as u64 - 1]),
+        ));
+    }
+    if page + 1 < pages {
+        navigation.push(InlineKeyboardButton::callback(
+            NEXT_PAGE,
+            callback_data(NEXT_PAGE, &[proposal_id, page as u64 + 1]),
+        ));
+    }
+    if !navigation.is_empty() {
+        keyboard = keyboard.append_row(navigation);
+    }
+
+    keyboard.append_row(vec![InlineKeyboardButton::callback(
+        COMMENT,
+        callback_data(COMMENT, &[proposal_id]),
+    )])
+}
+
+/// Keyboard under the prompt for the reason of a veto
+pub fn veto_prompt_keyboard(proposal_id: u64) -> InlineKeyboardMarkup {
+    InlineKeyboardMarkup::new(vec![vec![InlineKeyboardButton::callback(
+        CANCEL_VETO,
+        callback_data(CANCEL_VETO, &[proposal_id]),
+    )]])
+}
+
+/// Keyboard of a list of proposals, the browser or search results: an Open button per
+/// listed proposal, then the page navigation. Pages are counted from 0 and `page_data` is
+/// the callback data of a page
+pub fn browser_keyboard(
+    proposal_ids: &[u64],
+    page: usize,
+    pages: usize,
+    page_data: impl Fn(usize) -> String,
+) -> InlineKeyboardMarkup {
+    let mut keyboard = InlineKeyboardMarkup::new(proposal_ids.iter().map(|&proposal_id| {
+        vec![InlineKeyboardButton::callback(
+            format!("{} #{}", OPEN, proposal_id),
+            callback_data(OPEN, &[proposal_id]),
+        )]
+    }));
+
+    if pages > 1 {
+        let mut navigation = vec![];
+        if page > 0 {
+            navigation.push(InlineKeyboardButton::callback(
+                PREVIOUS_PAGE,
+                page_data(page - 1),
+            ));
+        }
+        navigation.push(InlineKeyboardButton::callback(
+            format!("{}/{}", page + 1, pages),
+            callback_data(PAGE_INDICATOR, &[]),
+        ));
+        if page + 1 < pages {
+            navigation.push(InlineKeyboardButton::callback(
+                NEXT_PAGE,
+                page_data(page + 1),
+            ));
+        }
+        keyboard = keyboard.append_row(navigation);
+    }
+    keyboard
+}
+
+/// Orders are sent by their index in `ProposalSort::ALL`
+fn sort_index(sort: ProposalSort) -> u64 {
+    ProposalSort::ALL
+        .iter()
+        .position(|&other| other == sort)
+        .unwrap_or_default() as u64
+}
+
+fn sort_at(index: u64) -> Option<ProposalSort> {
+    ProposalSort::ALL.get(index as usize).copied()
+}
+
+/// Buttons picking the order of the proposal browser, the current one checked
+pub fn sort_rows(current: ProposalSort, category: Option<&str>) -> Vec<Vec<InlineKeyboardButton>> {
+    let buttons = ProposalSort::ALL
+        .iter()
+        .map(|&sort| {
+            let label = match sort {
+                ProposalSort::Newest => SORT_NEWEST,
+                ProposalSort::MostVotes => SORT_MOST_VOTES,
+                ProposalSort::EndingSoonest => SORT_ENDING_SOONEST,
+                ProposalSort::MostDiscussed => SORT_MOST_DISCUSSED,
+            };
+            let label = match sort == current {
+                true => format!("{} {}", SELECTED, label),
+                false => label.to_string(),
+            };
+            InlineKeyboardButton::callback(
+                label,
+                callback_text_data(SORT, &[sort_index(sort), sort_index(current)], category),
+            )
+        })
+        .collect::<Vec<_>>();
+    buttons.chunks(2).map(|row| row.to_vec()).collect()
+}
+
+/// Callback data of a page of the proposal browser. The order travels with the page, so
+/// that whoever clicks gets the next page of the listing shown
+pub fn browse_data(page: usize, sort: ProposalSort, category: Option<&str>) -> String {
+    callback_text_data(BROWSE, &[page as u64, sort_index(sort)], category)
+}
+
+/// One button per chat category, plus one to see every proposal
+pub fn category_filter_keyboard(categories: &[String]) -> InlineKeyboardMarkup {
+    let mut buttons = vec![InlineKeyboardButton::callback(
+        format!("{} {}", FILTER, ALL_CATEGORIES),
+        callback_data(FILTER, &[]),
+    )];
+    buttons.extend(categories.iter().map(|category| {
+        InlineKeyboardButton::callback(
+            format!("{} {}", FILTER, category),
+            callback_text_data(FILTER, &[], Some(category)),
+        )
+    }));
+
+    InlineKeyboardMarkup::new(buttons.chunks(3).map(|row| row.to_vec()))
+}
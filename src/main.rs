@@ -1,11 +1,19 @@
 mod bot;
 mod consts;
+mod cron;
+mod dates;
 mod errors;
 mod handler;
+mod ical;
 mod keyboards;
 mod messages;
+mod recurring;
+mod scheduler;
+mod search;
 mod storage;
+mod templates;
 mod utils;
+mod webhook;
 use tracing_subscriber::EnvFilter;
 
 pub use self::errors::TgError;
@@ -21,7 +29,9 @@ pub async fn main() -> Result<(), TgError> {
     log::info!("Starting bot...");
 
     let bot = bot::TgBot::new();
-    let _ = bot.init().await;
+    if let Err(err) = bot.init().await {
+        log::error!("{}", err);
+    }
 
     Ok(())
 }
@@ -1,56 +1,267 @@
+use crate::consts::{
+    BALLOT, BOT_NAME, COMMENTS_PER_PAGE, DESCRIPTION, EXPIRATION_DATE, MAX_LISTED_PROPOSALS,
+    PROPOSAL_LINK_PREFIX, STARTING_DATE,
+};
+use crate::dates::format_date;
+use crate::storage::{Ballot, Comment, Proposal, ProposalSort, ProposalStatus};
+use crate::templates::ProposalTemplate;
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
 use regex::Regex;
+use teloxide::utils::markdown::escape;
+
 pub fn get_welcome_message() -> String {
     "ZuzaRule: Crowdsourced Governance at Your Fingertips\nOur platform is where community consensus builds the foundation of collaboration\nStay updated, propose changes, and have a direct hand in sculpting the environment you participate in, all within your Telegram group\nEmbrace the power of collective decision making with ZuzaRule"
         .to_string()
 }
 
-pub fn get_new_proposal_message() -> String {
-    let template =
-        "Create your proposal below:\nTitle: \nDescription: \nStarting Date: \nExpiration Date: \n";
-    template.to_string()
+pub fn get_new_proposal_message(template: &ProposalTemplate) -> String {
+    escape(&get_new_draft_text(template))
+}
+
+/// Text of an empty draft, before escaping
+pub fn get_new_draft_text(template: &ProposalTemplate) -> String {
+    let mut message = match template.name == ProposalTemplate::default().name {
+        true => "Create your proposal below:\n".to_string(),
+        false => format!("Create your {} proposal below:\n", template.name),
+    };
+    for field in &template.fields {
+        message.push_str(&format!("{}: \n", field.name));
+    }
+    message
 }
 
-struct MessageFields<'a> {
-    pub title: Option<&'a str>,
-    pub description: Option<&'a str>,
-    pub starting_date: Option<&'a str>,
-    pub expiration_date: Option<&'a str>,
+/// Fills in (or replaces) the value of a field in a proposal draft
+pub fn parse_message(msg: &str, field_name: &str, value: &str) -> String {
+    let pattern = format!(r"(?m)^{}:[^\n]*$", regex::escape(field_name));
+    let re = Regex::new(&pattern).unwrap();
+    re.replace(msg, regex::NoExpand(&format!("{}: {}", field_name, value)))
+        .to_string()
 }
 
-fn fill_in_message_template(template: &str, fields: MessageFields) -> String {
-    let mut message = template.to_string();
+/// Reads the value of a field back from a proposal draft
+pub fn extract_field(msg: &str, field_name: &str) -> String {
+    let pattern = format!(r"(?m)^{}:([^\n]*)$", regex::escape(field_name));
+    let re = Regex::new(&pattern).unwrap();
+    re.captures(msg)
+        .and_then(|caps| caps.get(1).map(|m| m.as_str().trim().to_string()))
+        .unwrap_or_default()
+}
 
-    let replace_placeholder = |message: &mut String, field_name: &str, value: Option<&str>| {
-        if let Some(val) = value {
-            let pattern = format!(r"{}: \n", field_name);
-            let re = Regex::new(&pattern).unwrap();
-            *message = re
-                .replace(message, format!("{}: {}\n", field_name, val))
-                .to_string();
-        }
+/// Link opening the proposal in a private chat with the bot
+pub fn proposal_link(proposal_id: u64) -> String {
+    format!(
+        "https://t.me/{}?start={}{}",
+        BOT_NAME, PROPOSAL_LINK_PREFIX, proposal_id
+    )
+}
+
+/// Renders a stored proposal as a card, with its dates in `timezone`, escaped for MarkdownV2
+pub fn get_proposal_message(proposal: &Proposal, comments: usize, timezone: Tz) -> String {
+    let mut message = format!(
+        "Proposal #{}\nStatus: {}\nTitle: {}\nAuthor: {}\n",
+        proposal.id, proposal.status, proposal.title, proposal.author,
+    );
+    let date = |date: Option<DateTime<Utc>>| {
+        date.map(|date| format_date(&date, timezone))
+            .unwrap_or_default()
     };
+    let fields = [
+        (DESCRIPTION, proposal.description.clone()),
+        (STARTING_DATE, date(proposal.starting_date)),
+        (EXPIRATION_DATE, date(proposal.expiration_date)),
+    ];
+    for (name, value) in fields.into_iter().chain(
+        proposal
+            .fields
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.clone())),
+    ) {
+        if !value.is_empty() {
+            message.push_str(&format!("{}: {}\n", name, value));
+        }
+    }
+    if !proposal.tags.is_empty() {
+        message.push_str(&format!("Tags: {}\n", proposal.tags.join(", ")));
+    }
+    if proposal.ballot != Ballot::Buttons {
+        message.push_str(&format!("{}: {}\n", BALLOT, proposal.ballot));
+    }
+
+    if proposal.status == ProposalStatus::SeekingSponsors || !proposal.sponsors.is_empty() {
+        let sponsors = match proposal.sponsors.is_empty() {
+            true => "none yet".to_string(),
+            false => proposal.sponsor_names(),
+        };
+        message.push_str(&format!(
+            "Sponsors ({}/{}): {}\n",
+            proposal.sponsors.len(),
+            proposal.sponsors_required,
+            sponsors
+        ));
+    }
+    if let Some(reason) = &proposal.veto_reason {
+        message.push_str(&format!("Veto reason: {}\n", reason));
+    }
+    message.push_str(&format!(
+        "Votes: 👍 {} / 👎 {}\nComments: {}",
+        proposal.vote, proposal.votes_against, comments
+    ));
 
-    replace_placeholder(&mut message, "Title", fields.title);
-    replace_placeholder(&mut message, "Description", fields.description);
-    replace_placeholder(&mut message, "Starting Date", fields.starting_date);
-    replace_placeholder(&mut message, "Expiration Date", fields.expiration_date);
+    escape(&message)
+}
+
+/// Announces how the vote on a closed proposal ended
+pub fn get_result_message(proposal: &Proposal) -> String {
+    format!(
+        "Voting on Proposal #{} \"{}\" has ended: {} (👍 {} / 👎 {})",
+        proposal.id, proposal.title, proposal.status, proposal.vote, proposal.votes_against
+    )
+}
 
+/// Breaks down the outcome of a closed proposal, with its dates in `timezone`
+pub fn get_detailed_results_message(proposal: &Proposal, timezone: Tz) -> String {
+    let cast = proposal.vote + proposal.votes_against;
+    let share = |votes: u64| match cast {
+        0 => 0,
+        cast => (votes * 100 + cast / 2) / cast,
+    };
+    let mut message = format!(
+        "Results of Proposal #{} \"{}\"\nOutcome: {}\n👍 For: {} ({}%)\n👎 Against: {} ({}%)\nVotes cast: {}\n",
+        proposal.id,
+        proposal.title,
+        proposal.status,
+        proposal.vote,
+        share(proposal.vote),
+        proposal.votes_against,
+        share(proposal.votes_against),
+        cast
+    );
+    if let (Some(start), Some(end)) = (proposal.starting_date, proposal.expiration_date) {
+        message.push_str(&format!(
+            "Voting: {} to {}\n",
+            format_date(&start, timezone),
+            format_date(&end, timezone)
+        ));
+    }
+    if proposal.ballot != Ballot::Buttons {
+        message.push_str(&format!("{}: {}\n", BALLOT, proposal.ballot));
+    }
+    if !proposal.sponsors.is_empty() {
+        message.push_str(&format!("Sponsors: {}\n", proposal.sponsor_names()));
+    }
+    if let Some(reason) = &proposal.veto_reason {
+        message.push_str(&format!("Veto reason: {}\n", reason));
+    }
+    if !proposal.history.is_empty() {
+        message.push_str("History:\n");
+        for event in &proposal.history {
+            message.push_str(&format!("- {}\n", event));
+        }
+    }
+    message
+}
+
+/// Lists proposals one line each, with a detail about each of them
+pub fn get_proposal_list_message(heading: &str, proposals: &[(&Proposal, String)]) -> String {
+    let mut message = format!("{}\n", heading);
+    for (proposal, detail) in proposals.iter().take(MAX_LISTED_PROPOSALS) {
+        message.push_str(&format!(
+            "#{} {}: {}\n",
+            proposal.id, proposal.title, detail
+        ));
+    }
+    if proposals.len() > MAX_LISTED_PROPOSALS {
+        message.push_str(&format!(
+            "…and {} older\n",
+            proposals.len() - MAX_LISTED_PROPOSALS
+        ));
+    }
     message
 }
 
-pub fn parse_message(
-    msg: &str,
-    title: Option<&str>,
-    description: Option<&str>,
-    starting_date: Option<&str>,
-    expiration_date: Option<&str>,
+/// Renders a page of the proposal browser, counted from 0
+pub fn get_browser_message(
+    proposals: &[Proposal],
+    page: usize,
+    pages: usize,
+    category: Option<&str>,
+    sort: ProposalSort,
 ) -> String {
-    let message_field = MessageFields {
-        title,
-        description,
-        starting_date,
-        expiration_date,
+    let mut message = match category {
+        Some(category) => format!("📚 Proposals tagged {}", category),
+        None => "📚 Proposals".to_string(),
     };
-    let filled_in_message = fill_in_message_template(msg, message_field);
-    filled_in_message
+    message.push_str(&format!(", {} (page {}/{})\n\n", sort, page + 1, pages));
+    if proposals.is_empty() {
+        message.push_str("No proposals yet.\n");
+    }
+    push_proposal_summaries(&mut message, proposals);
+    message.trim_end().to_string()
+}
+
+/// Renders a page of search results, counted from 0, out of `found` results
+pub fn get_search_results_message(
+    query: &str,
+    proposals: &[Proposal],
+    found: usize,
+    page: usize,
+    pages: usize,
+) -> String {
+    let mut message = format!("🔍 {}\n", query);
+    match found {
+        0 => message.push_str("No proposals match.\n"),
+        found => message.push_str(&format!(
+            "{} found, best matches first (page {}/{})\n\n",
+            found,
+            page + 1,
+            pages
+        )),
+    }
+    push_proposal_summaries(&mut message, proposals);
+    message.trim_end().to_string()
+}
+
+/// Adds a short summary of each proposal to a list
+fn push_proposal_summaries(message: &mut String, proposals: &[Proposal]) {
+    for proposal in proposals {
+        message.push_str(&format!(
+            "#{} {}\n{}, 👍 {} / 👎 {}\n\n",
+            proposal.id, proposal.title, proposal.status, proposal.vote, proposal.votes_against
+        ));
+    }
+}
+
+/// Renders one page of a proposal's discussion thread, escaped for MarkdownV2
+pub fn get_discussion_message(proposal: &Proposal, comments: &[Comment], page: usize) -> String {
+    let pages = comments.len().div_ceil(COMMENTS_PER_PAGE).max(1);
+    let mut message = format!(
+        "💬 Discussion of Proposal #{}: {}\nPage {}/{}\n\n",
+        proposal.id,
+        proposal.title,
+        page + 1,
+        pages
+    );
+
+    if comments.is_empty() {
+        message.push_str("No comments yet.\n");
+    }
+    for comment in comments
+        .iter()
+        .skip(page * COMMENTS_PER_PAGE)
+        .take(COMMENTS_PER_PAGE)
+    {
+        message.push_str(&format!("{}: {}\n", comment.author, comment.text));
+    }
+    message.push_str("\nReply to the proposal card or to this message to comment.");
+
+    escape(&message)
+}
+
+/// Splits a comma separated tag list into normalized tags
+pub fn parse_tags(text: &str) -> Vec<String> {
+    text.split(',')
+        .map(|tag| tag.trim().to_lowercase())
+        .filter(|tag| !tag.is_empty())
+        .collect()
 }
@@ -0,0 +1,183 @@
+use crate::consts::{BOT_NAME, DESCRIPTION, TAGS, TITLE};
+use crate::cron::Schedule;
+use crate::dates::{format_date, unit_duration};
+use crate::messages::parse_tags;
+use crate::storage::{Ballot, Proposal, ProposalStatus};
+use chrono::{DateTime, Duration, Utc};
+use chrono_tz::Tz;
+use lazy_static::lazy_static;
+use regex::Regex;
+use std::fmt;
+use teloxide::types::ChatId;
+
+const SCHEDULE: &str = "Schedule";
+const VOTING: &str = "Voting";
+const DEFAULT_VOTING_DAYS: i64 = 7;
+const MAX_VOTING_DAYS: i64 = 365;
+
+lazy_static! {
+    static ref VOTING_LENGTH: Regex = Regex::new(r"^(\d+)\s*(minute|hour|day|week)s?$").unwrap();
+}
+
+/// A proposal the scheduler opens again every period of its schedule
+#[derive(Debug, Clone)]
+pub(crate) struct RecurringProposal {
+    pub(crate) title: String,
+    pub(crate) chat_id: ChatId,
+    pub(crate) schedule: Schedule,
+    /// How long each proposal stays open for voting
+    pub(crate) voting: Duration,
+    /// Values copied into every proposal, e.g. the Description, Tags or custom fields
+    pub(crate) fields: Vec<(String, String)>,
+    /// When the next proposal opens, `None` once the schedule never fires again
+    pub(crate) next_run: Option<DateTime<Utc>>,
+}
+
+/// Parses a voting length such as "7 days" or "36 hours", up to a year
+fn parse_voting(text: &str) -> Result<Duration, String> {
+    let caps = VOTING_LENGTH
+        .captures(text.trim())
+        .ok_or_else(|| format!("\"{}\" should look like \"7 days\" or \"36 hours\"", text))?;
+    let amount = caps[1]
+        .parse::<u64>()
+        .ok()
+        .filter(|amount| *amount > 0)
+        .ok_or_else(|| format!("\"{}\" has to be longer than that", text))?;
+    unit_duration(amount, &caps[2])
+        .filter(|voting| *voting <= Duration::days(MAX_VOTING_DAYS))
+        .ok_or_else(|| format!("Voting can last at most {} days", MAX_VOTING_DAYS))
+}
+
+impl RecurringProposal {
+    /// Parses a definition: the title on the first line, then a "Schedule: <cron>" line, an
+    /// optional "Voting: <length>" line and "Field: value" lines copied into every proposal
+    pub(crate) fn parse(
+        definition: &str,
+        chat_id: ChatId,
+        now: DateTime<Utc>,
+        timezone: Tz,
+    ) -> Result<Self, String> {
+        let mut lines = definition
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty());
+        let title = lines
+            .next()
+            .ok_or_else(|| "The recurring proposal needs a title".to_string())?
+            .to_string();
+
+        let mut schedule = None;
+        let mut voting = Duration::days(DEFAULT_VOTING_DAYS);
+        let mut fields = vec![];
+        for line in lines {
+            let (name, value) = line
+                .split_once(':')
+                .map(|(name, value)| (name.trim(), value.trim()))
+                .ok_or_else(|| format!("\"{}\" should look like \"Field: value\"", line))?;
+            match name {
+                _ if name.eq_ignore_ascii_case(SCHEDULE) => {
+                    schedule = Some(Schedule::parse(value)?)
+                }
+                _ if name.eq_ignore_ascii_case(VOTING) => voting = parse_voting(value)?,
+                _ if name.eq_ignore_ascii_case(TITLE) => {
+                    return Err("The title goes on the first line".to_string())
+                }
+                _ if value.is_empty() => {}
+                _ => fields.push((name.to_string(), value.to_string())),
+            }
+        }
+        let schedule = schedule.ok_or_else(|| {
+            "Add a \"Schedule: minute hour day month weekday\" line, e.g. Schedule: 0 9 1 * *"
+                .to_string()
+        })?;
+        let next_run = schedule.next_after(now, timezone);
+        if next_run.is_none() {
+            return Err(format!("The schedule {} never fires", schedule));
+        }
+
+        Ok(RecurringProposal {
+            title,
+            chat_id,
+            schedule,
+            voting,
+            fields,
+            next_run,
+        })
+    }
+
+    /// The proposal for the period starting `now`, open for voting right away
+    pub(crate) fn proposal(
+        &self,
+        id: u64,
+        now: DateTime<Utc>,
+        timezone: Tz,
+    ) -> Result<Proposal, String> {
+        let expiration_date = now
+            .checked_add_signed(self.voting)
+            .ok_or_else(|| format!("The voting of \"{}\" would never end", self.title))?;
+        let mut description = String::new();
+        let mut tags = vec![];
+        let mut fields = vec![];
+        for (name, value) in &self.fields {
+            match name.as_str() {
+                _ if name.eq_ignore_ascii_case(DESCRIPTION) => description = value.clone(),
+                _ if name.eq_ignore_ascii_case(TAGS) => tags = parse_tags(value),
+                _ => fields.push((name.clone(), value.clone())),
+            }
+        }
+        let period = now.with_timezone(&timezone).format("%Y-%m-%d");
+
+        Ok(Proposal {
+            id,
+            chat_id: self.chat_id,
+            author_id: None,
+            author: BOT_NAME.to_string(),
+            title: format!("{} ({})", self.title, period),
+            description,
+            starting_date: Some(now),
+            expiration_date: Some(expiration_date),
+            fields,
+            vote: 0,
+            votes_against: 0,
+            voters: vec![],
+            ballot: Ballot::Buttons,
+            poll: None,
+            topic: None,
+            card: None,
+            card_pinned: false,
+            pinned_results: None,
+            tags,
+            status: ProposalStatus::Active,
+            sponsors: vec![],
+            sponsors_required: 0,
+            veto_reason: None,
+            history: vec![format!("Opened on schedule {}", self.schedule)],
+        })
+    }
+
+    /// Describes the recurring proposal, with its next run shown in `timezone`
+    pub(crate) fn summary(&self, timezone: Tz) -> String {
+        let next_run = match self.next_run {
+            Some(next_run) => format_date(&next_run, timezone),
+            None => "never".to_string(),
+        };
+        format!("{}\n  Next: {}", self, next_run)
+    }
+}
+
+impl fmt::Display for RecurringProposal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.title)?;
+        write!(f, "\n  {}: {}", SCHEDULE, self.schedule)?;
+        let voting = match self.voting.num_minutes() {
+            minutes if minutes % (24 * 60) == 0 => format!("{} days", minutes / (24 * 60)),
+            minutes if minutes % 60 == 0 => format!("{} hours", minutes / 60),
+            minutes => format!("{} minutes", minutes),
+        };
+        write!(f, "\n  {}: {}", VOTING, voting)?;
+        for (name, value) in &self.fields {
+            write!(f, "\n  {}: {}", name, value)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,118 @@
+use crate::consts::SCHEDULER_INTERVAL;
+use crate::dates::chat_timezone;
+use crate::handler::poll_handlers::{open_proposal_poll, stop_proposal_poll, sync_poll_tally};
+use crate::handler::{
+    announce_result, edit_proposal_card, publish_proposal, unpin_expired_results,
+};
+use crate::messages::get_result_message;
+use crate::recurring::RecurringProposal;
+use crate::storage::{
+    Ballot, Proposal, ProposalStatus, TgProposalStorage, TgRecurringStorage,
+    GLOBAL_PROPOSAL_STORAGE, GLOBAL_RECURRING_STORAGE,
+};
+use crate::TgError;
+use chrono::{DateTime, Utc};
+use teloxide::Bot;
+use tokio::time::interval;
+
+/// Runs the periodic jobs for as long as the bot is up
+pub(crate) async fn run_scheduler(bot: Bot) {
+    let mut ticks = interval(SCHEDULER_INTERVAL);
+    loop {
+        ticks.tick().await;
+        let now = Utc::now();
+        for recurring in GLOBAL_RECURRING_STORAGE.due(now) {
+            if let Err(err) = open_recurring_proposal(&bot, recurring, now).await {
+                log::warn!("could not open a recurring proposal: {}", err);
+            }
+        }
+        for mut proposal in polls_due(now) {
+            if let Err(err) = open_proposal_poll(&bot, &mut proposal).await {
+                log::warn!(
+                    "could not open the poll of proposal {}: {}",
+                    proposal.id,
+                    err
+                );
+            }
+        }
+        for proposal in expired_proposals(now) {
+            if let Err(err) = close_expired_proposal(&bot, proposal).await {
+                log::warn!("could not close an expired proposal: {}", err);
+            }
+        }
+        for proposal in GLOBAL_PROPOSAL_STORAGE.all() {
+            unpin_expired_results(&bot, &proposal, now).await;
+        }
+    }
+}
+
+/// Opens the proposal of the current period and moves the schedule on to the next one,
+/// unless an admin removed or redefined the recurring proposal in the meantime
+async fn open_recurring_proposal(
+    bot: &Bot,
+    recurring: RecurringProposal,
+    now: DateTime<Utc>,
+) -> Result<(), TgError> {
+    let timezone = chat_timezone(recurring.chat_id);
+    let next_run = recurring.schedule.next_after(now, timezone);
+    if !GLOBAL_RECURRING_STORAGE.advance(&recurring, next_run) {
+        return Ok(());
+    }
+    let mut proposal = recurring
+        .proposal(GLOBAL_PROPOSAL_STORAGE.next_id(), now, timezone)
+        .map_err(TgError::Parse)?;
+    GLOBAL_PROPOSAL_STORAGE.insert(proposal.clone());
+
+    publish_proposal(bot, &mut proposal).await
+}
+
+/// Poll proposals whose voting started without a poll to vote in
+fn polls_due(now: DateTime<Utc>) -> Vec<Proposal> {
+    GLOBAL_PROPOSAL_STORAGE
+        .all()
+        .into_iter()
+        .filter(|proposal| {
+            matches!(proposal.ballot, Ballot::Poll { .. })
+                && proposal.poll.is_none()
+                && proposal.voting_refusal(now).is_none()
+        })
+        .collect()
+}
+
+/// Active proposals whose expiration date has passed
+fn expired_proposals(now: DateTime<Utc>) -> Vec<Proposal> {
+    GLOBAL_PROPOSAL_STORAGE
+        .all()
+        .into_iter()
+        .filter(|proposal| {
+            proposal.status == ProposalStatus::Active
+                && proposal.expiration_date.is_some_and(|date| date <= now)
+        })
+        .collect()
+}
+
+/// Stops the poll if there is one, counts the final tally, takes the vote buttons off the
+/// card and announces the result
+async fn close_expired_proposal(bot: &Bot, proposal: Proposal) -> Result<(), TgError> {
+    let poll = stop_proposal_poll(bot, &proposal).await;
+    // an admin may have closed or vetoed it in the meantime
+    let closed = GLOBAL_PROPOSAL_STORAGE.modify(proposal.id, |proposal| {
+        if proposal.status != ProposalStatus::Active {
+            return None;
+        }
+        if let Some(poll) = &poll {
+            sync_poll_tally(proposal, poll);
+        }
+        proposal.close("the expiration date");
+        Some(proposal.clone())
+    });
+    let Some(mut proposal) = closed.flatten() else {
+        return Ok(());
+    };
+    if let Some(card) = proposal.card {
+        edit_proposal_card(bot, proposal.chat_id, card, &proposal).await?;
+    }
+
+    let announcement = get_result_message(&proposal);
+    announce_result(bot, &mut proposal, announcement).await
+}
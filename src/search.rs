@@ -0,0 +1,254 @@
+use crate::dates::parse_date;
+use crate::storage::{
+    Proposal, ProposalStatus, TgProposalStorage, TgSearchIndexStorage, GLOBAL_PROPOSAL_STORAGE,
+    GLOBAL_SEARCH_INDEX,
+};
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use hashbrown::HashMap;
+
+/// Words a text is indexed and searched by: lowercase runs of letters and digits
+pub(crate) fn search_words(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+/// A /search query: the words to look for and the filters results have to pass
+#[derive(Debug, Clone, Default)]
+pub(crate) struct SearchQuery {
+    pub(crate) terms: Vec<String>,
+    pub(crate) status: Option<ProposalStatus>,
+    /// Only proposals whose voting window ends on or after this date
+    pub(crate) from: Option<DateTime<Utc>>,
+    /// Only proposals whose voting window starts on or before this date
+    pub(crate) to: Option<DateTime<Utc>>,
+}
+
+impl SearchQuery {
+    /// Parses e.g. "budget status:passed from:2024-01-01 to:2024-06-30", dates are read in
+    /// `timezone`
+    pub(crate) fn parse(text: &str, now: DateTime<Utc>, timezone: Tz) -> Result<Self, String> {
+        let mut query = SearchQuery::default();
+        for word in text.split_whitespace() {
+            match word.split_once(':') {
+                Some((filter, value)) if filter.eq_ignore_ascii_case("status") => {
+                    query.status = Some(parse_status(value)?)
+                }
+                Some((filter, value)) if filter.eq_ignore_ascii_case("from") => {
+                    query.from = Some(parse_date(value, now, timezone)?)
+                }
+                Some((filter, value)) if filter.eq_ignore_ascii_case("to") => {
+                    query.to = Some(parse_date(value, now, timezone)?)
+                }
+                _ => query.terms.extend(search_words(word)),
+            }
+        }
+        if query.terms.is_empty()
+            && query.status.is_none()
+            && query.from.is_none()
+            && query.to.is_none()
+        {
+            return Err("Tell me what to search for".to_string());
+        }
+        Ok(query)
+    }
+
+    /// Whether the proposal passes the status and date filters
+    fn admits(&self, proposal: &Proposal) -> bool {
+        if self.status.is_some_and(|status| status != proposal.status) {
+            return false;
+        }
+        if self.from.is_none() && self.to.is_none() {
+            return true;
+        }
+        // proposals without a voting window can't be placed in a date range
+        let start = proposal.starting_date.or(proposal.expiration_date);
+        let end = proposal.expiration_date.or(proposal.starting_date);
+        match (start, end) {
+            (Some(start), Some(end)) => {
+                self.from.is_none_or(|from| end >= from) && self.to.is_none_or(|to| start <= to)
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Reads a status filter, e.g. "passed" or "sponsors"
+fn parse_status(text: &str) -> Result<ProposalStatus, String> {
+    match text.to_lowercase().as_str() {
+        "sponsors" | "seeking" => Ok(ProposalStatus::SeekingSponsors),
+        "active" | "open" => Ok(ProposalStatus::Active),
+        "passed" => Ok(ProposalStatus::Passed),
+        "rejected" => Ok(ProposalStatus::Rejected),
+        "vetoed" => Ok(ProposalStatus::Vetoed),
+        _ => Err(format!(
+            "Unknown status \"{}\", use sponsors, active, passed, rejected or vetoed",
+            text
+        )),
+    }
+}
+
+/// The proposals matching the query among those `visible` says may be shown, best match
+/// first. Without search words every proposal passing the filters matches, newest first
+pub(crate) fn search_proposals(
+    query: &SearchQuery,
+    visible: impl Fn(&Proposal) -> bool,
+) -> Vec<Proposal> {
+    let candidates = GLOBAL_PROPOSAL_STORAGE
+        .all()
+        .into_iter()
+        .filter(|proposal| visible(proposal) && query.admits(proposal));
+    if query.terms.is_empty() {
+        return candidates.rev().collect();
+    }
+
+    let scores = GLOBAL_SEARCH_INDEX
+        .search(&query.terms)
+        .into_iter()
+        .collect::<HashMap<_, _>>();
+    let mut found = candidates
+        .filter_map(|proposal| Some((*scores.get(&proposal.id)?, proposal)))
+        .collect::<Vec<_>>();
+    found.sort_by(|(a_score, a), (b_score, b)| b_score.cmp(a_score).then(b.id.cmp(&a.id)));
+    found.into_iter().map(|(_, proposal)| proposal).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{Ballot, SearchIndex};
+    use chrono::TimeZone;
+    use teloxide::types::ChatId;
+
+    fn utc(year: i32, month: u32, day: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(year, month, day, 0, 0, 0).unwrap()
+    }
+
+    fn parse(text: &str) -> Result<SearchQuery, String> {
+        SearchQuery::parse(text, utc(2024, 5, 1), Tz::UTC)
+    }
+
+    fn proposal(id: u64, title: &str, description: &str, tags: &[&str]) -> Proposal {
+        Proposal {
+            id,
+            chat_id: ChatId(-1),
+            author_id: None,
+            author: "author".to_string(),
+            title: title.to_string(),
+            description: description.to_string(),
+            starting_date: None,
+            expiration_date: None,
+            fields: vec![],
+            vote: 0,
+            votes_against: 0,
+            voters: vec![],
+            ballot: Ballot::default(),
+            poll: None,
+            topic: None,
+            card: None,
+            card_pinned: false,
+            pinned_results: None,
+            tags: tags.iter().map(|tag| tag.to_string()).collect(),
+            status: ProposalStatus::Active,
+            sponsors: vec![],
+            sponsors_required: 0,
+            veto_reason: None,
+            history: vec![],
+        }
+    }
+
+    fn search(index: &SearchIndex, terms: &str) -> Vec<(u64, u32)> {
+        let mut found = index.search(&search_words(terms));
+        found.sort();
+        found
+    }
+
+    #[test]
+    fn parses_terms_and_filters() {
+        let query = parse("Budget road-works STATUS:Passed from:2024-01-01 to:2024-06-30").unwrap();
+        assert_eq!(query.terms, ["budget", "road", "works"]);
+        assert_eq!(query.status, Some(ProposalStatus::Passed));
+        assert_eq!(query.from, Some(utc(2024, 1, 1)));
+        assert_eq!(query.to, Some(utc(2024, 6, 30)));
+
+        let query = parse("status:sponsors").unwrap();
+        assert!(query.terms.is_empty());
+        assert_eq!(query.status, Some(ProposalStatus::SeekingSponsors));
+        // words with a colon that aren't filters are searched for
+        assert_eq!(parse("10:30").unwrap().terms, ["10", "30"]);
+    }
+
+    #[test]
+    fn rejects_empty_and_invalid_queries() {
+        assert!(parse("").is_err());
+        assert!(parse(" -- ").is_err());
+        assert!(parse("status:maybe").is_err());
+        assert!(parse("budget from:someday").is_err());
+    }
+
+    #[test]
+    fn filters_by_status_and_voting_window() {
+        let mut may = proposal(1, "Budget", "", &[]);
+        may.starting_date = Some(utc(2024, 5, 1));
+        may.expiration_date = Some(utc(2024, 5, 8));
+        let undated = proposal(2, "Budget", "", &[]);
+
+        let passed = parse("status:passed").unwrap();
+        assert!(!passed.admits(&may));
+        assert!(parse("status:active").unwrap().admits(&may));
+
+        assert!(parse("from:2024-05-05").unwrap().admits(&may));
+        assert!(!parse("from:2024-05-09").unwrap().admits(&may));
+        assert!(parse("to:2024-05-01").unwrap().admits(&may));
+        assert!(!parse("to:2024-04-30").unwrap().admits(&may));
+        assert!(!parse("from:2024-01-01").unwrap().admits(&undated));
+        assert!(parse("budget").unwrap().admits(&undated));
+    }
+
+    #[test]
+    fn ranks_by_where_words_show_up() {
+        let index = SearchIndex::new();
+        index.index_proposal(&proposal(1, "Park benches", "", &[]));
+        index.index_proposal(&proposal(2, "New lights", "Benches for the park", &[]));
+        index.index_proposal(&proposal(3, "Repairs", "", &["benches"]));
+        index.index_comment(3, "The benches are broken, the bench by the pond too");
+
+        // title 5, description 2, tag 3 plus 1 for the comment, "bench" doesn't match
+        assert_eq!(search(&index, "benches"), [(1, 5), (2, 2), (3, 4)]);
+        // every term has to match, the scores add up
+        assert_eq!(search(&index, "park benches"), [(1, 10), (2, 4)]);
+        assert!(search(&index, "park repairs").is_empty());
+    }
+
+    #[test]
+    fn matches_word_prefixes() {
+        let index = SearchIndex::new();
+        index.index_proposal(&proposal(1, "Bench", "", &[]));
+        index.index_proposal(&proposal(2, "Benches", "", &[]));
+        index.index_proposal(&proposal(3, "Bend", "", &[]));
+        index.index_proposal(&proposal(4, "Abench", "", &[]));
+
+        assert_eq!(search(&index, "bench"), [(1, 5), (2, 5)]);
+        assert_eq!(search(&index, "BEN"), [(1, 5), (2, 5), (3, 5)]);
+        assert!(search(&index, "benchmark").is_empty());
+    }
+
+    #[test]
+    fn reindexes_and_removes_proposals() {
+        let index = SearchIndex::new();
+        index.index_proposal(&proposal(1, "Old title", "", &[]));
+        index.index_comment(1, "title");
+        index.index_proposal(&proposal(1, "New name", "", &[]));
+
+        assert!(search(&index, "old").is_empty());
+        assert_eq!(search(&index, "name"), [(1, 5)]);
+        // comments stay when the fields are indexed again
+        assert_eq!(search(&index, "title"), [(1, 1)]);
+
+        index.remove(1);
+        assert!(search(&index, "name").is_empty());
+        assert!(search(&index, "title").is_empty());
+    }
+}
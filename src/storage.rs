@@ -1,17 +1,29 @@
 #![allow(dead_code)]
+use crate::consts::{
+    BALLOT_ANONYMOUS_POLL, BALLOT_BUTTONS, BALLOT_POLL, DEFAULT_PIN_RESULTS_HOURS,
+    DEFAULT_SPONSOR_THRESHOLD,
+};
+use crate::recurring::RecurringProposal;
+use crate::search::search_words;
+use crate::templates::ProposalTemplate;
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
 use hashbrown::HashMap;
 use lazy_static::lazy_static;
 use parking_lot::RwLock;
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
-use teloxide::types::{ChatId, Message, MessageId};
+use teloxide::types::{ChatId, Message, MessageId, UserId};
 
 lazy_static! {
     pub(crate) static ref GLOBAL_MAIN_MENU_STORAGE: MainMenuStorage = MainMenuStorage::new();
 }
 
 lazy_static! {
-    pub(crate) static ref GLOBAL_CREATE_PROPOSAL_STORAGE: ProposalMenuStorage =
-        TgMessageStorage::new();
+    pub(crate) static ref GLOBAL_DRAFT_STORAGE: DraftStorage = TgDraftStorage::new();
 }
 
 lazy_static! {
@@ -19,6 +31,49 @@ lazy_static! {
     pub(crate) static ref GLOBAL_PROPOSAL_STORAGE: ProposalStorage = TgProposalStorage::new();
 }
 
+lazy_static! {
+    pub(crate) static ref GLOBAL_COMMENT_STORAGE: CommentStorage = TgCommentStorage::new();
+}
+
+lazy_static! {
+    pub(crate) static ref GLOBAL_TEMPLATE_STORAGE: TemplateStorage = TgTemplateStorage::new();
+}
+
+lazy_static! {
+    pub(crate) static ref GLOBAL_CHAT_SETTINGS_STORAGE: ChatSettingsStorage =
+        TgChatSettingsStorage::new();
+}
+
+lazy_static! {
+    pub(crate) static ref GLOBAL_RECURRING_STORAGE: RecurringStorage = TgRecurringStorage::new();
+}
+
+lazy_static! {
+    pub(crate) static ref GLOBAL_USER_SETTINGS_STORAGE: UserSettingsStorage =
+        TgUserSettingsStorage::new();
+}
+
+lazy_static! {
+    pub(crate) static ref GLOBAL_ADMIN_STORAGE: AdminStorage = TgAdminStorage::new();
+}
+
+lazy_static! {
+    pub(crate) static ref GLOBAL_VETO_STORAGE: VetoStorage = TgVetoStorage::new();
+}
+
+lazy_static! {
+    pub(crate) static ref GLOBAL_SEARCH_INDEX: SearchIndex = TgSearchIndexStorage::new();
+}
+
+lazy_static! {
+    pub(crate) static ref GLOBAL_SAVED_SEARCH_STORAGE: SavedSearchStorage =
+        TgSavedSearchStorage::new();
+}
+
+lazy_static! {
+    pub(crate) static ref GLOBAL_MEMBERSHIP_STORAGE: MembershipStorage = TgMembershipStorage::new();
+}
+
 pub(crate) trait TgMessageStorage {
     fn new() -> Self;
     fn insert(&self, user_name: String, message: TgMessage);
@@ -27,26 +82,245 @@ pub(crate) trait TgMessageStorage {
     fn delete_all(&self);
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ProposalStatus {
+    /// Submitted, waiting for enough co-sponsors before voting opens
+    SeekingSponsors,
+    /// Open for voting
+    Active,
+    /// Voting closed with more votes for than against
+    Passed,
+    /// Voting closed without a majority for it
+    Rejected,
+    /// Stopped by an admin, see `Proposal::veto_reason`
+    Vetoed,
+}
+
+impl fmt::Display for ProposalStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Self::SeekingSponsors => write!(f, "Seeking sponsors"),
+            Self::Active => write!(f, "Active"),
+            Self::Passed => write!(f, "Passed"),
+            Self::Rejected => write!(f, "Rejected"),
+            Self::Vetoed => write!(f, "Vetoed"),
+        }
+    }
+}
+
+/// How members vote on a proposal
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub(crate) enum Ballot {
+    /// 👍 and 👎 buttons under the proposal card
+    #[default]
+    Buttons,
+    /// A native Telegram poll posted once voting opens
+    Poll { anonymous: bool },
+}
+
+impl Ballot {
+    /// Reads the value of the Ballot field, see `BALLOT_CHOICES`
+    pub(crate) fn parse(text: &str) -> Self {
+        match text.trim().to_lowercase().as_str() {
+            BALLOT_POLL => Self::Poll { anonymous: false },
+            BALLOT_ANONYMOUS_POLL => Self::Poll { anonymous: true },
+            _ => Self::Buttons,
+        }
+    }
+}
+
+impl fmt::Display for Ballot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Self::Buttons => write!(f, "{}", BALLOT_BUTTONS),
+            Self::Poll { anonymous: false } => write!(f, "{}", BALLOT_POLL),
+            Self::Poll { anonymous: true } => write!(f, "{}", BALLOT_ANONYMOUS_POLL),
+        }
+    }
+}
+
+/// The Telegram poll a proposal is voted on
+#[derive(Debug, Clone)]
+pub(crate) struct ProposalPoll {
+    pub(crate) poll_id: String,
+    pub(crate) message_id: MessageId,
+}
+
 #[derive(Debug, Clone)]
 pub(crate) struct Proposal {
+    pub(crate) id: u64,
+    pub(crate) chat_id: ChatId,
+    /// The member who submitted the proposal, `None` for proposals the scheduler opened
+    pub(crate) author_id: Option<UserId>,
+    /// Name shown for the author on cards
+    pub(crate) author: String,
     pub(crate) title: String,
     pub(crate) description: String,
-    pub(crate) starting_date: String,
-    pub(crate) expiration_date: String,
+    pub(crate) starting_date: Option<DateTime<Utc>>,
+    pub(crate) expiration_date: Option<DateTime<Utc>>,
+    /// Values of the custom fields of the template the proposal was created from
+    pub(crate) fields: Vec<(String, String)>,
     pub(crate) vote: u64,
+    pub(crate) votes_against: u64,
+    /// Who voted and which way, so every member votes once. Anonymous polls don't say
+    pub(crate) voters: Vec<(UserId, bool)>,
+    pub(crate) ballot: Ballot,
+    /// Set once the poll of a `Ballot::Poll` proposal is posted
+    pub(crate) poll: Option<ProposalPoll>,
+    /// Forum topic of the proposal, in chats with topics enabled
+    pub(crate) topic: Option<i32>,
+    /// The card posted to the proposal's chat when it was published
+    pub(crate) card: Option<MessageId>,
+    pub(crate) card_pinned: bool,
+    /// The pinned results message and when it gets unpinned
+    pub(crate) pinned_results: Option<(MessageId, DateTime<Utc>)>,
+    pub(crate) tags: Vec<String>,
+    pub(crate) status: ProposalStatus,
+    /// Co-sponsors and the names shown for them, so every member sponsors once
+    pub(crate) sponsors: Vec<(UserId, String)>,
+    /// Number of co-sponsors the chat required when the proposal was submitted
+    pub(crate) sponsors_required: usize,
+    pub(crate) veto_reason: Option<String>,
+    /// What happened to the proposal, oldest first
+    pub(crate) history: Vec<String>,
+}
+
+impl Proposal {
+    /// Why votes aren't taken at `now`, if they aren't. The scheduler closes expired
+    /// proposals, until then the dates decide
+    pub(crate) fn voting_refusal(&self, now: DateTime<Utc>) -> Option<&'static str> {
+        match self.status {
+            ProposalStatus::Active if self.starting_date.is_some_and(|date| now < date) => {
+                Some("Voting on this proposal has not started yet")
+            }
+            ProposalStatus::Active if self.expiration_date.is_some_and(|date| date <= now) => {
+                Some("Voting on this proposal has ended")
+            }
+            ProposalStatus::Active => None,
+            ProposalStatus::SeekingSponsors => Some("Voting has not opened for this proposal yet"),
+            _ => Some("Voting on this proposal has ended"),
+        }
+    }
+
+    /// Counts the member's vote, or moves it when they change their mind.
+    /// Returns false when the member already voted that way
+    pub(crate) fn vote(&mut self, voter: UserId, in_favour: bool) -> bool {
+        match self
+            .voters
+            .iter_mut()
+            .find(|(user_id, _)| *user_id == voter)
+        {
+            Some((_, previous)) if *previous == in_favour => return false,
+            Some((_, previous)) => {
+                *previous = in_favour;
+                match in_favour {
+                    true => self.votes_against -= 1,
+                    false => self.vote -= 1,
+                }
+            }
+            None => self.voters.push((voter, in_favour)),
+        }
+        match in_favour {
+            true => self.vote += 1,
+            false => self.votes_against += 1,
+        }
+        true
+    }
+
+    /// Takes back the member's vote, e.g. when they retract their poll answer.
+    /// Returns false when the member hadn't voted
+    pub(crate) fn retract_vote(&mut self, voter: UserId) -> bool {
+        let Some(index) = self
+            .voters
+            .iter()
+            .position(|(user_id, _)| *user_id == voter)
+        else {
+            return false;
+        };
+        match self.voters.remove(index).1 {
+            true => self.vote -= 1,
+            false => self.votes_against -= 1,
+        }
+        true
+    }
+
+    /// Ends the vote, the proposal passes with more votes for than against
+    pub(crate) fn close(&mut self, closed_by: &str) {
+        self.status = match self.vote > self.votes_against {
+            true => ProposalStatus::Passed,
+            false => ProposalStatus::Rejected,
+        };
+        self.history.push(format!(
+            "Voting closed by {}: {} (👍 {} / 👎 {})",
+            closed_by, self.status, self.vote, self.votes_against
+        ));
+    }
+
+    /// Names of the co-sponsors, in the order they joined
+    pub(crate) fn sponsor_names(&self) -> String {
+        self.sponsors
+            .iter()
+            .map(|(_, name)| name.as_str())
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    pub(crate) fn veto(&mut self, vetoed_by: &str, reason: &str) {
+        self.status = ProposalStatus::Vetoed;
+        self.veto_reason = Some(reason.to_string());
+        self.history
+            .push(format!("Vetoed by {}: {}", vetoed_by, reason));
+    }
 }
 
 pub(crate) trait TgProposalStorage {
     fn new() -> Self;
-    fn insert(&self, user_name: String, message: Proposal);
-    fn get(&self, user_name: String) -> Option<Vec<Proposal>>;
-    fn remove(&self, user_name: String) -> Option<Proposal>;
+    /// Stores the proposal under its author
+    fn insert(&self, proposal: Proposal);
+    /// The proposals the member submitted
+    fn get(&self, author: UserId) -> Option<Vec<Proposal>>;
+    fn remove(&self, author: UserId) -> Option<Proposal>;
     fn delete_all(&self);
+    fn next_id(&self) -> u64;
+    fn get_by_id(&self, id: u64) -> Option<Proposal>;
+    fn update(&self, proposal: Proposal);
+    /// Changes the stored proposal under the write lock and returns what `change` returned,
+    /// so changes made while a handler awaits Telegram aren't overwritten
+    fn modify<R>(&self, id: u64, change: impl FnOnce(&mut Proposal) -> R) -> Option<R>;
+    /// Forgets the proposal's pinned results, unless another announcement was pinned since.
+    /// Only that field changes, so concurrent updates of the proposal are kept
+    fn clear_pinned_results(&self, id: u64, results: MessageId);
+    /// Every member's proposals, oldest first
+    fn all(&self) -> Vec<Proposal>;
+    /// The proposal voted on with the given Telegram poll
+    fn get_by_poll_id(&self, poll_id: &str) -> Option<Proposal>;
 }
 
 #[derive(Debug, Default)]
 pub(crate) struct ProposalStorage {
-    storage: Arc<RwLock<HashMap<String, Vec<Proposal>>>>,
+    storage: Arc<RwLock<HashMap<Option<UserId>, Vec<Proposal>>>>,
+    next_id: AtomicU64,
+}
+
+/// A comment left on a proposal's discussion thread
+#[derive(Debug, Clone)]
+pub(crate) struct Comment {
+    pub(crate) author: String,
+    pub(crate) text: String,
+}
+
+pub(crate) trait TgCommentStorage {
+    fn new() -> Self;
+    fn insert(&self, proposal_id: u64, comment: Comment);
+    fn get(&self, proposal_id: u64) -> Option<Vec<Comment>>;
+    fn count(&self, proposal_id: u64) -> usize;
+    fn remove(&self, proposal_id: u64) -> Option<Vec<Comment>>;
+    fn delete_all(&self);
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct CommentStorage {
+    storage: Arc<RwLock<HashMap<u64, Vec<Comment>>>>,
 }
 
 #[derive(Debug, Clone)]
@@ -60,23 +334,25 @@ impl TgProposalStorage for ProposalStorage {
     fn new() -> Self {
         ProposalStorage {
             storage: Arc::new(RwLock::new(HashMap::new())),
+            next_id: AtomicU64::new(1),
         }
     }
 
-    fn insert(&self, user_name: String, proposal: Proposal) {
+    fn insert(&self, proposal: Proposal) {
+        GLOBAL_SEARCH_INDEX.index_proposal(&proposal);
         let mut storage = self.storage.write();
         storage
-            .entry(user_name)
-            .or_insert_with(Vec::new)
+            .entry(proposal.author_id)
+            .or_default()
             .push(proposal);
     }
 
-    fn get(&self, user_name: String) -> Option<Vec<Proposal>> {
+    fn get(&self, author: UserId) -> Option<Vec<Proposal>> {
         let storage = self.storage.read();
-        storage.get(&user_name).cloned()
+        storage.get(&Some(author)).cloned()
     }
 
-    fn remove(&self, _user_name: String) -> Option<Proposal> {
+    fn remove(&self, _author: UserId) -> Option<Proposal> {
         todo!()
     }
 
@@ -84,33 +360,878 @@ impl TgProposalStorage for ProposalStorage {
         let mut storage = self.storage.write();
         storage.clear();
     }
+
+    fn next_id(&self) -> u64 {
+        self.next_id.fetch_add(1, Ordering::SeqCst)
+    }
+
+    fn get_by_id(&self, id: u64) -> Option<Proposal> {
+        let storage = self.storage.read();
+        storage
+            .values()
+            .flatten()
+            .find(|proposal| proposal.id == id)
+            .cloned()
+    }
+
+    fn update(&self, proposal: Proposal) {
+        let mut storage = self.storage.write();
+        if let Some(stored) = storage
+            .values_mut()
+            .flatten()
+            .find(|stored| stored.id == proposal.id)
+        {
+            GLOBAL_SEARCH_INDEX.index_proposal(&proposal);
+            *stored = proposal;
+        }
+    }
+
+    fn modify<R>(&self, id: u64, change: impl FnOnce(&mut Proposal) -> R) -> Option<R> {
+        let mut storage = self.storage.write();
+        let stored = storage
+            .values_mut()
+            .flatten()
+            .find(|stored| stored.id == id)?;
+        let result = change(stored);
+        GLOBAL_SEARCH_INDEX.index_proposal(stored);
+        Some(result)
+    }
+
+    fn clear_pinned_results(&self, id: u64, results: MessageId) {
+        let mut storage = self.storage.write();
+        if let Some(stored) = storage.values_mut().flatten().find(|stored| {
+            stored.id == id
+                && stored
+                    .pinned_results
+                    .is_some_and(|(pinned, _)| pinned == results)
+        }) {
+            stored.pinned_results = None;
+        }
+    }
+
+    fn all(&self) -> Vec<Proposal> {
+        let storage = self.storage.read();
+        let mut proposals = storage.values().flatten().cloned().collect::<Vec<_>>();
+        proposals.sort_by_key(|proposal| proposal.id);
+        proposals
+    }
+
+    fn get_by_poll_id(&self, poll_id: &str) -> Option<Proposal> {
+        let storage = self.storage.read();
+        storage
+            .values()
+            .flatten()
+            .find(|proposal| {
+                proposal
+                    .poll
+                    .as_ref()
+                    .is_some_and(|poll| poll.poll_id == poll_id)
+            })
+            .cloned()
+    }
+}
+
+impl TgCommentStorage for CommentStorage {
+    fn new() -> Self {
+        CommentStorage {
+            storage: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+
+    fn insert(&self, proposal_id: u64, comment: Comment) {
+        GLOBAL_SEARCH_INDEX.index_comment(proposal_id, &comment.text);
+        let mut storage = self.storage.write();
+        storage.entry(proposal_id).or_default().push(comment);
+    }
+
+    fn get(&self, proposal_id: u64) -> Option<Vec<Comment>> {
+        let storage = self.storage.read();
+        storage.get(&proposal_id).cloned()
+    }
+
+    fn count(&self, proposal_id: u64) -> usize {
+        let storage = self.storage.read();
+        storage.get(&proposal_id).map_or(0, Vec::len)
+    }
+
+    fn remove(&self, proposal_id: u64) -> Option<Vec<Comment>> {
+        let mut storage = self.storage.write();
+        storage.remove(&proposal_id)
+    }
+
+    fn delete_all(&self) {
+        let mut storage = self.storage.write();
+        storage.clear();
+    }
+}
+
+/// Whether the cards of active proposals get pinned, and if members get notified
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub(crate) enum PinMode {
+    #[default]
+    Off,
+    On,
+    Silent,
+}
+
+impl fmt::Display for PinMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Self::Off => write!(f, "off"),
+            Self::On => write!(f, "on"),
+            Self::Silent => write!(f, "silent"),
+        }
+    }
+}
+
+/// Per chat governance settings
+#[derive(Debug, Clone)]
+pub(crate) struct ChatSettings {
+    pub(crate) sponsor_threshold: usize,
+    /// Categories proposals can be tagged with and filtered by in See Proposals
+    pub(crate) categories: Vec<String>,
+    /// Zone typed dates are read in and dates are shown in
+    pub(crate) timezone: Tz,
+    pub(crate) pin_mode: PinMode,
+    /// How long the results of a vote stay pinned, 0 doesn't pin them
+    pub(crate) pin_results_hours: u32,
+    /// Channel new proposals and results are cross-posted to
+    pub(crate) channel: Option<ChatId>,
+    /// Days a member has to be in the chat before voting or proposing, 0 for no minimum
+    pub(crate) min_membership_days: u32,
+}
+
+impl Default for ChatSettings {
+    fn default() -> Self {
+        ChatSettings {
+            sponsor_threshold: DEFAULT_SPONSOR_THRESHOLD,
+            categories: vec![],
+            timezone: Tz::UTC,
+            pin_mode: PinMode::Off,
+            pin_results_hours: DEFAULT_PIN_RESULTS_HOURS,
+            channel: None,
+            min_membership_days: 0,
+        }
+    }
+}
+
+impl ChatSettings {
+    /// Applies a /pin setting: "on", "silent", "off" or "results <hours>"
+    pub(crate) fn set_pinning(&mut self, text: &str) -> Result<(), String> {
+        let words = text.split_whitespace().collect::<Vec<_>>();
+        match words[..] {
+            ["on"] => self.pin_mode = PinMode::On,
+            ["silent"] => self.pin_mode = PinMode::Silent,
+            ["off"] => self.pin_mode = PinMode::Off,
+            ["results", hours] => {
+                self.pin_results_hours = hours
+                    .parse::<u32>()
+                    .map_err(|_| format!("\"{}\" is not a number of hours", hours))?
+            }
+            _ => {
+                return Err(
+                    "Usage: /pin on, /pin silent, /pin off or /pin results <hours>".to_string(),
+                )
+            }
+        }
+        Ok(())
+    }
+
+    /// Describes the pinning settings
+    pub(crate) fn pinning(&self) -> String {
+        let results = match self.pin_results_hours {
+            0 => "results are not pinned".to_string(),
+            hours => format!("results stay pinned for {} hour(s)", hours),
+        };
+        format!("Pinning active proposals is {}, {}", self.pin_mode, results)
+    }
+
+    /// Describes who may vote and propose
+    pub(crate) fn membership_age(&self) -> String {
+        match self.min_membership_days {
+            0 => "Every member can vote and propose".to_string(),
+            days => format!(
+                "Members can vote and propose once they've been in the chat for {} day(s). \
+                 Members who joined before the bot was made an admin or last restarted count \
+                 as old enough",
+                days
+            ),
+        }
+    }
+}
+
+pub(crate) trait TgChatSettingsStorage {
+    fn new() -> Self;
+    fn insert(&self, chat_id: ChatId, settings: ChatSettings);
+    /// Returns the chat's settings, or the defaults if the chat never changed them
+    fn get(&self, chat_id: ChatId) -> ChatSettings;
+    fn remove(&self, chat_id: ChatId) -> Option<ChatSettings>;
+    fn delete_all(&self);
 }
 
 #[derive(Debug, Default)]
-pub(crate) struct ProposalMenuStorage {
-    storage: Arc<RwLock<HashMap<String, TgMessage>>>,
+pub(crate) struct ChatSettingsStorage {
+    storage: Arc<RwLock<HashMap<ChatId, ChatSettings>>>,
 }
 
-impl TgMessageStorage for ProposalMenuStorage {
+impl TgChatSettingsStorage for ChatSettingsStorage {
     fn new() -> Self {
-        ProposalMenuStorage {
+        ChatSettingsStorage {
             storage: Arc::new(RwLock::new(HashMap::new())),
         }
     }
 
-    fn insert(&self, user_name: String, message: TgMessage) {
+    fn insert(&self, chat_id: ChatId, settings: ChatSettings) {
         let mut storage = self.storage.write();
-        storage.insert(user_name, message);
+        storage.insert(chat_id, settings);
     }
 
-    fn get(&self, user_name: String) -> Option<TgMessage> {
+    fn get(&self, chat_id: ChatId) -> ChatSettings {
         let storage = self.storage.read();
-        storage.get(&user_name).cloned()
+        storage.get(&chat_id).cloned().unwrap_or_default()
     }
 
-    fn remove(&self, user_name: String) -> Option<TgMessage> {
+    fn remove(&self, chat_id: ChatId) -> Option<ChatSettings> {
         let mut storage = self.storage.write();
-        storage.remove(&user_name)
+        storage.remove(&chat_id)
+    }
+
+    fn delete_all(&self) {
+        let mut storage = self.storage.write();
+        storage.clear();
+    }
+}
+
+/// Order proposals are listed in by See Proposals
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub(crate) enum ProposalSort {
+    #[default]
+    Newest,
+    MostVotes,
+    /// Proposals still open for voting whose expiration date is closest
+    EndingSoonest,
+    MostDiscussed,
+}
+
+impl ProposalSort {
+    /// Every order, in the order the sort buttons show them
+    pub(crate) const ALL: [ProposalSort; 4] = [
+        Self::Newest,
+        Self::MostVotes,
+        Self::EndingSoonest,
+        Self::MostDiscussed,
+    ];
+
+    /// Sorts proposals in this order. Proposals that rank the same stay newest first
+    pub(crate) fn sort(self, proposals: &mut [Proposal], now: DateTime<Utc>) {
+        proposals.sort_by_key(|proposal| Reverse(proposal.id));
+        match self {
+            Self::Newest => {}
+            Self::MostVotes => {
+                proposals.sort_by_key(|proposal| Reverse(proposal.vote + proposal.votes_against))
+            }
+            // proposals that already ended or have no end come after the open ones
+            Self::EndingSoonest => proposals.sort_by_key(|proposal| {
+                match (proposal.status, proposal.expiration_date) {
+                    (
+                        ProposalStatus::Active | ProposalStatus::SeekingSponsors,
+                        Some(expiration_date),
+                    ) if expiration_date >= now => (false, Some(expiration_date)),
+                    _ => (true, None),
+                }
+            }),
+            // counted once per proposal, each count takes the comment storage lock
+            Self::MostDiscussed => proposals
+                .sort_by_cached_key(|proposal| Reverse(GLOBAL_COMMENT_STORAGE.count(proposal.id))),
+        }
+    }
+}
+
+impl fmt::Display for ProposalSort {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Self::Newest => write!(f, "newest first"),
+            Self::MostVotes => write!(f, "most votes first"),
+            Self::EndingSoonest => write!(f, "ending soonest first"),
+            Self::MostDiscussed => write!(f, "most discussed first"),
+        }
+    }
+}
+
+/// Per user preferences. The time zone applies in the user's private chat with the bot, the
+/// sort order wherever they browse proposals
+#[derive(Debug, Clone, Default)]
+pub(crate) struct UserSettings {
+    /// Overrides the chat's time zone
+    pub(crate) timezone: Option<Tz>,
+    pub(crate) proposal_sort: ProposalSort,
+}
+
+pub(crate) trait TgUserSettingsStorage {
+    fn new() -> Self;
+    fn insert(&self, user_id: UserId, settings: UserSettings);
+    /// Returns the user's settings, or the defaults if the user never changed them
+    fn get(&self, user_id: UserId) -> UserSettings;
+    fn remove(&self, user_id: UserId) -> Option<UserSettings>;
+    fn delete_all(&self);
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct UserSettingsStorage {
+    storage: Arc<RwLock<HashMap<UserId, UserSettings>>>,
+}
+
+impl TgUserSettingsStorage for UserSettingsStorage {
+    fn new() -> Self {
+        UserSettingsStorage {
+            storage: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+
+    fn insert(&self, user_id: UserId, settings: UserSettings) {
+        let mut storage = self.storage.write();
+        storage.insert(user_id, settings);
+    }
+
+    fn get(&self, user_id: UserId) -> UserSettings {
+        let storage = self.storage.read();
+        storage.get(&user_id).cloned().unwrap_or_default()
+    }
+
+    fn remove(&self, user_id: UserId) -> Option<UserSettings> {
+        let mut storage = self.storage.write();
+        storage.remove(&user_id)
+    }
+
+    fn delete_all(&self) {
+        let mut storage = self.storage.write();
+        storage.clear();
+    }
+}
+
+/// A proposal being written in its author's private chat with the bot
+#[derive(Debug, Clone)]
+pub(crate) struct Draft {
+    /// Chat the proposal is published to once submitted
+    pub(crate) chat_id: ChatId,
+    /// The draft message, `None` while the author picks a template
+    pub(crate) message_id: Option<MessageId>,
+    /// Text of the draft message, holding the values filled in so far
+    pub(crate) text: String,
+    /// The template the draft was started from, `None` while the author picks one
+    pub(crate) template: Option<ProposalTemplate>,
+}
+
+pub(crate) trait TgDraftStorage {
+    fn new() -> Self;
+    /// Starts a new draft, replacing the author's previous one
+    fn insert(&self, author: UserId, draft: Draft);
+    fn get(&self, author: UserId) -> Option<Draft>;
+    fn remove(&self, author: UserId) -> Option<Draft>;
+    fn delete_all(&self);
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct DraftStorage {
+    storage: Arc<RwLock<HashMap<UserId, Draft>>>,
+}
+
+impl TgDraftStorage for DraftStorage {
+    fn new() -> Self {
+        DraftStorage {
+            storage: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+
+    fn insert(&self, author: UserId, draft: Draft) {
+        let mut storage = self.storage.write();
+        storage.insert(author, draft);
+    }
+
+    fn get(&self, author: UserId) -> Option<Draft> {
+        let storage = self.storage.read();
+        storage.get(&author).cloned()
+    }
+
+    fn remove(&self, author: UserId) -> Option<Draft> {
+        let mut storage = self.storage.write();
+        storage.remove(&author)
+    }
+
+    fn delete_all(&self) {
+        let mut storage = self.storage.write();
+        storage.clear();
+    }
+}
+
+pub(crate) trait TgTemplateStorage {
+    fn new() -> Self;
+    /// Adds the template, replacing the chat's template with the same name
+    fn insert(&self, chat_id: ChatId, template: ProposalTemplate);
+    fn get(&self, chat_id: ChatId) -> Vec<ProposalTemplate>;
+    fn remove(&self, chat_id: ChatId, name: &str) -> Option<ProposalTemplate>;
+    fn delete_all(&self);
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct TemplateStorage {
+    storage: Arc<RwLock<HashMap<ChatId, Vec<ProposalTemplate>>>>,
+}
+
+impl TgTemplateStorage for TemplateStorage {
+    fn new() -> Self {
+        TemplateStorage {
+            storage: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+
+    fn insert(&self, chat_id: ChatId, template: ProposalTemplate) {
+        let mut storage = self.storage.write();
+        let templates = storage.entry(chat_id).or_default();
+        match templates
+            .iter_mut()
+            .find(|stored| stored.name.eq_ignore_ascii_case(&template.name))
+        {
+            Some(stored) => *stored = template,
+            None => templates.push(template),
+        }
+    }
+
+    fn get(&self, chat_id: ChatId) -> Vec<ProposalTemplate> {
+        let storage = self.storage.read();
+        storage.get(&chat_id).cloned().unwrap_or_default()
+    }
+
+    fn remove(&self, chat_id: ChatId, name: &str) -> Option<ProposalTemplate> {
+        let mut storage = self.storage.write();
+        let templates = storage.get_mut(&chat_id)?;
+        let index = templates
+            .iter()
+            .position(|stored| stored.name.eq_ignore_ascii_case(name))?;
+        Some(templates.remove(index))
+    }
+
+    fn delete_all(&self) {
+        let mut storage = self.storage.write();
+        storage.clear();
+    }
+}
+
+pub(crate) trait TgRecurringStorage {
+    fn new() -> Self;
+    /// Adds the recurring proposal, replacing the chat's one with the same title
+    fn insert(&self, chat_id: ChatId, recurring: RecurringProposal);
+    fn get(&self, chat_id: ChatId) -> Vec<RecurringProposal>;
+    fn remove(&self, chat_id: ChatId, title: &str) -> Option<RecurringProposal>;
+    /// Recurring proposals of every chat whose next run is at or before `now`
+    fn due(&self, now: DateTime<Utc>) -> Vec<RecurringProposal>;
+    /// Moves the schedule of `due` on to `next_run`, `false` when it was removed or redefined
+    /// since `due` was read
+    fn advance(&self, due: &RecurringProposal, next_run: Option<DateTime<Utc>>) -> bool;
+    fn delete_all(&self);
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct RecurringStorage {
+    storage: Arc<RwLock<HashMap<ChatId, Vec<RecurringProposal>>>>,
+}
+
+impl TgRecurringStorage for RecurringStorage {
+    fn new() -> Self {
+        RecurringStorage {
+            storage: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+
+    fn insert(&self, chat_id: ChatId, recurring: RecurringProposal) {
+        let mut storage = self.storage.write();
+        let recurrings = storage.entry(chat_id).or_default();
+        match recurrings
+            .iter_mut()
+            .find(|stored| stored.title.eq_ignore_ascii_case(&recurring.title))
+        {
+            Some(stored) => *stored = recurring,
+            None => recurrings.push(recurring),
+        }
+    }
+
+    fn get(&self, chat_id: ChatId) -> Vec<RecurringProposal> {
+        let storage = self.storage.read();
+        storage.get(&chat_id).cloned().unwrap_or_default()
+    }
+
+    fn remove(&self, chat_id: ChatId, title: &str) -> Option<RecurringProposal> {
+        let mut storage = self.storage.write();
+        let recurrings = storage.get_mut(&chat_id)?;
+        let index = recurrings
+            .iter()
+            .position(|stored| stored.title.eq_ignore_ascii_case(title))?;
+        Some(recurrings.remove(index))
+    }
+
+    fn due(&self, now: DateTime<Utc>) -> Vec<RecurringProposal> {
+        let storage = self.storage.read();
+        storage
+            .values()
+            .flatten()
+            .filter(|recurring| recurring.next_run.is_some_and(|next_run| next_run <= now))
+            .cloned()
+            .collect()
+    }
+
+    fn advance(&self, due: &RecurringProposal, next_run: Option<DateTime<Utc>>) -> bool {
+        let mut storage = self.storage.write();
+        let stored = storage.get_mut(&due.chat_id).and_then(|recurrings| {
+            recurrings.iter_mut().find(|stored| {
+                stored.next_run == due.next_run && stored.to_string() == due.to_string()
+            })
+        });
+        match stored {
+            Some(stored) => {
+                stored.next_run = next_run;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn delete_all(&self) {
+        let mut storage = self.storage.write();
+        storage.clear();
+    }
+}
+
+/// Administrators of a chat, as last fetched from Telegram
+#[derive(Debug, Clone)]
+pub(crate) struct ChatAdmins {
+    pub(crate) admins: Vec<UserId>,
+    pub(crate) fetched_at: DateTime<Utc>,
+}
+
+pub(crate) trait TgAdminStorage {
+    fn new() -> Self;
+    fn insert(&self, chat_id: ChatId, admins: ChatAdmins);
+    fn get(&self, chat_id: ChatId) -> Option<ChatAdmins>;
+    /// Adds or drops the user from the chat's cached admins, if the chat is cached
+    fn update(&self, chat_id: ChatId, user_id: UserId, is_admin: bool);
+    fn remove(&self, chat_id: ChatId) -> Option<ChatAdmins>;
+    fn delete_all(&self);
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct AdminStorage {
+    storage: Arc<RwLock<HashMap<ChatId, ChatAdmins>>>,
+}
+
+impl TgAdminStorage for AdminStorage {
+    fn new() -> Self {
+        AdminStorage {
+            storage: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+
+    fn insert(&self, chat_id: ChatId, admins: ChatAdmins) {
+        let mut storage = self.storage.write();
+        storage.insert(chat_id, admins);
+    }
+
+    fn get(&self, chat_id: ChatId) -> Option<ChatAdmins> {
+        let storage = self.storage.read();
+        storage.get(&chat_id).cloned()
+    }
+
+    fn update(&self, chat_id: ChatId, user_id: UserId, is_admin: bool) {
+        let mut storage = self.storage.write();
+        if let Some(cached) = storage.get_mut(&chat_id) {
+            cached.admins.retain(|admin| *admin != user_id);
+            if is_admin {
+                cached.admins.push(user_id);
+            }
+        }
+    }
+
+    fn remove(&self, chat_id: ChatId) -> Option<ChatAdmins> {
+        let mut storage = self.storage.write();
+        storage.remove(&chat_id)
+    }
+
+    fn delete_all(&self) {
+        let mut storage = self.storage.write();
+        storage.clear();
+    }
+}
+
+/// A veto waiting for the written reason of the admin who clicked it
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct PendingVeto {
+    pub(crate) proposal_id: u64,
+    /// The card the veto was clicked on, updated once the veto happens
+    pub(crate) card: MessageId,
+    /// The prompt asking for the reason
+    pub(crate) prompt: MessageId,
+}
+
+pub(crate) trait TgVetoStorage {
+    fn new() -> Self;
+    fn insert(&self, chat_id: ChatId, admin: UserId, veto: PendingVeto);
+    fn get(&self, chat_id: ChatId, admin: UserId) -> Option<PendingVeto>;
+    fn remove(&self, chat_id: ChatId, admin: UserId) -> Option<PendingVeto>;
+    fn delete_all(&self);
+}
+
+/// Pending vetoes by chat and admin, so other members of the chat can't give the reason
+#[derive(Debug, Default)]
+pub(crate) struct VetoStorage {
+    storage: Arc<RwLock<HashMap<(ChatId, UserId), PendingVeto>>>,
+}
+
+impl TgVetoStorage for VetoStorage {
+    fn new() -> Self {
+        VetoStorage {
+            storage: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+
+    fn insert(&self, chat_id: ChatId, admin: UserId, veto: PendingVeto) {
+        let mut storage = self.storage.write();
+        storage.insert((chat_id, admin), veto);
+    }
+
+    fn get(&self, chat_id: ChatId, admin: UserId) -> Option<PendingVeto> {
+        let storage = self.storage.read();
+        storage.get(&(chat_id, admin)).copied()
+    }
+
+    fn remove(&self, chat_id: ChatId, admin: UserId) -> Option<PendingVeto> {
+        let mut storage = self.storage.write();
+        storage.remove(&(chat_id, admin))
+    }
+
+    fn delete_all(&self) {
+        let mut storage = self.storage.write();
+        storage.clear();
+    }
+}
+
+/// How much a word counts towards a match, by where it shows up
+const TITLE_WEIGHT: u32 = 5;
+const TAG_WEIGHT: u32 = 3;
+const DESCRIPTION_WEIGHT: u32 = 2;
+const COMMENT_WEIGHT: u32 = 1;
+
+/// How much a word weighs in a proposal, in its own fields and in its comments. Comments
+/// are kept apart so the fields can be indexed again when the proposal changes
+#[derive(Debug, Clone, Copy, Default)]
+struct Posting {
+    fields: u32,
+    comments: u32,
+}
+
+#[derive(Debug, Default)]
+struct Index {
+    /// Proposals each word shows up in, sorted so that words sharing a prefix are adjacent
+    postings: BTreeMap<String, HashMap<u64, Posting>>,
+    /// Words of each proposal's own fields, to take them out again
+    fields: HashMap<u64, Vec<String>>,
+}
+
+pub(crate) trait TgSearchIndexStorage {
+    fn new() -> Self;
+    /// Indexes the title, description and tags of the proposal, replacing what was indexed
+    /// for them before
+    fn index_proposal(&self, proposal: &Proposal);
+    fn index_comment(&self, proposal_id: u64, text: &str);
+    /// Proposals with a word starting with each of the terms, with how well they match
+    fn search(&self, terms: &[String]) -> Vec<(u64, u32)>;
+    fn remove(&self, proposal_id: u64);
+    fn delete_all(&self);
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct SearchIndex {
+    storage: Arc<RwLock<Index>>,
+}
+
+impl TgSearchIndexStorage for SearchIndex {
+    fn new() -> Self {
+        SearchIndex {
+            storage: Arc::new(RwLock::new(Index::default())),
+        }
+    }
+
+    fn index_proposal(&self, proposal: &Proposal) {
+        let mut storage = self.storage.write();
+        let index = &mut *storage;
+        for word in index.fields.remove(&proposal.id).unwrap_or_default() {
+            if let Some(posting) = index
+                .postings
+                .get_mut(&word)
+                .and_then(|proposals| proposals.get_mut(&proposal.id))
+            {
+                posting.fields = 0;
+            }
+        }
+
+        let weighted = [
+            (proposal.title.as_str(), TITLE_WEIGHT),
+            (proposal.description.as_str(), DESCRIPTION_WEIGHT),
+        ]
+        .into_iter()
+        .chain(proposal.tags.iter().map(|tag| (tag.as_str(), TAG_WEIGHT)));
+        let mut words = vec![];
+        for (text, weight) in weighted {
+            for word in search_words(text) {
+                let posting = index
+                    .postings
+                    .entry(word.clone())
+                    .or_default()
+                    .entry(proposal.id)
+                    .or_default();
+                posting.fields += weight;
+                words.push(word);
+            }
+        }
+        index.fields.insert(proposal.id, words);
+    }
+
+    fn index_comment(&self, proposal_id: u64, text: &str) {
+        let mut storage = self.storage.write();
+        for word in search_words(text) {
+            let posting = storage
+                .postings
+                .entry(word)
+                .or_default()
+                .entry(proposal_id)
+                .or_default();
+            posting.comments += COMMENT_WEIGHT;
+        }
+    }
+
+    fn search(&self, terms: &[String]) -> Vec<(u64, u32)> {
+        let storage = self.storage.read();
+        let mut scores: Option<HashMap<u64, u32>> = None;
+        for term in terms {
+            let mut matches = HashMap::new();
+            for (_, proposals) in storage
+                .postings
+                .range(term.clone()..)
+                .take_while(|(word, _)| word.starts_with(term.as_str()))
+            {
+                for (proposal_id, posting) in proposals {
+                    let weight = posting.fields + posting.comments;
+                    if weight > 0 {
+                        *matches.entry(*proposal_id).or_insert(0) += weight;
+                    }
+                }
+            }
+            // every term has to match
+            scores = Some(match scores {
+                None => matches,
+                Some(scores) => scores
+                    .into_iter()
+                    .filter_map(|(proposal_id, score)| {
+                        Some((proposal_id, score + matches.get(&proposal_id)?))
+                    })
+                    .collect(),
+            });
+        }
+        scores.unwrap_or_default().into_iter().collect()
+    }
+
+    fn remove(&self, proposal_id: u64) {
+        let mut storage = self.storage.write();
+        storage.fields.remove(&proposal_id);
+        for proposals in storage.postings.values_mut() {
+            proposals.remove(&proposal_id);
+        }
+    }
+
+    fn delete_all(&self) {
+        let mut storage = self.storage.write();
+        *storage = Index::default();
+    }
+}
+
+pub(crate) trait TgSavedSearchStorage {
+    fn new() -> Self;
+    /// Remembers the query whose results a message shows, to page through them
+    fn insert(&self, chat_id: ChatId, message_id: MessageId, query: String);
+    fn get(&self, chat_id: ChatId, message_id: MessageId) -> Option<String>;
+    fn remove(&self, chat_id: ChatId, message_id: MessageId) -> Option<String>;
+    fn delete_all(&self);
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct SavedSearchStorage {
+    storage: Arc<RwLock<HashMap<(ChatId, MessageId), String>>>,
+}
+
+impl TgSavedSearchStorage for SavedSearchStorage {
+    fn new() -> Self {
+        SavedSearchStorage {
+            storage: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+
+    fn insert(&self, chat_id: ChatId, message_id: MessageId, query: String) {
+        let mut storage = self.storage.write();
+        storage.insert((chat_id, message_id), query);
+    }
+
+    fn get(&self, chat_id: ChatId, message_id: MessageId) -> Option<String> {
+        let storage = self.storage.read();
+        storage.get(&(chat_id, message_id)).cloned()
+    }
+
+    fn remove(&self, chat_id: ChatId, message_id: MessageId) -> Option<String> {
+        let mut storage = self.storage.write();
+        storage.remove(&(chat_id, message_id))
+    }
+
+    fn delete_all(&self) {
+        let mut storage = self.storage.write();
+        storage.clear();
+    }
+}
+
+pub(crate) trait TgMembershipStorage {
+    fn new() -> Self;
+    /// Records when the user joined the chat
+    fn insert(&self, chat_id: ChatId, user_id: UserId, joined_at: DateTime<Utc>);
+    /// When the user joined, `None` for members who joined before the bot saw them
+    fn get(&self, chat_id: ChatId, user_id: UserId) -> Option<DateTime<Utc>>;
+    fn remove(&self, chat_id: ChatId, user_id: UserId) -> Option<DateTime<Utc>>;
+    fn delete_all(&self);
+}
+
+/// A member of a chat
+type Membership = (ChatId, UserId);
+
+#[derive(Debug, Default)]
+pub(crate) struct MembershipStorage {
+    storage: Arc<RwLock<HashMap<Membership, DateTime<Utc>>>>,
+}
+
+impl TgMembershipStorage for MembershipStorage {
+    fn new() -> Self {
+        MembershipStorage {
+            storage: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+
+    fn insert(&self, chat_id: ChatId, user_id: UserId, joined_at: DateTime<Utc>) {
+        let mut storage = self.storage.write();
+        storage.insert((chat_id, user_id), joined_at);
+    }
+
+    fn get(&self, chat_id: ChatId, user_id: UserId) -> Option<DateTime<Utc>> {
+        let storage = self.storage.read();
+        storage.get(&(chat_id, user_id)).copied()
+    }
+
+    fn remove(&self, chat_id: ChatId, user_id: UserId) -> Option<DateTime<Utc>> {
+        let mut storage = self.storage.write();
+        storage.remove(&(chat_id, user_id))
     }
 
     fn delete_all(&self) {
@@ -0,0 +1,428 @@
+use crate::consts::{
+    BALLOT, BALLOT_ANONYMOUS_POLL, BALLOT_BUTTONS, BALLOT_POLL, DESCRIPTION, EXPIRATION_DATE,
+    MAX_TEMPLATE_NAME_LENGTH, STARTING_DATE, TAGS, TITLE,
+};
+use crate::dates::{format_date, parse_date};
+use crate::storage::{Draft, TgTemplateStorage, GLOBAL_TEMPLATE_STORAGE};
+use chrono::Utc;
+use chrono_tz::Tz;
+use lazy_static::lazy_static;
+use regex::Regex;
+use std::fmt;
+use teloxide::types::ChatId;
+
+/// Answer to edits of a draft whose template was removed or redefined since it was started
+pub(crate) const STALE_DRAFT: &str =
+    "The template of this draft was changed or removed, please start a new draft";
+
+/// Fields every proposal has a place for, matched case-insensitively in definitions
+const BUILT_IN_FIELDS: [&str; 6] = [
+    TITLE,
+    DESCRIPTION,
+    STARTING_DATE,
+    EXPIRATION_DATE,
+    TAGS,
+    BALLOT,
+];
+
+lazy_static! {
+    /// The part of a field definition after the name: type, required flag and validator
+    static ref FIELD_SPEC: Regex =
+        Regex::new(r"(?i)^(choice\([^)]*\)|\w+)(\s+required)?(?:\s+/(.+)/)?$").unwrap();
+    static ref URL: Regex = Regex::new(r"^https?://[^\s/?#]+\.[^\s]+$").unwrap();
+}
+
+/// Kind of value a template field accepts
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum FieldType {
+    Text,
+    Number,
+    Url,
+    Date,
+    Choice(Vec<String>),
+}
+
+impl FieldType {
+    /// Parses a type from a template definition, e.g. "number" or "choice(yes, no)"
+    fn parse(text: &str) -> Option<Self> {
+        let text = text.trim().to_lowercase();
+        match text.as_str() {
+            "text" => Some(Self::Text),
+            "number" => Some(Self::Number),
+            "url" => Some(Self::Url),
+            "date" => Some(Self::Date),
+            _ => text
+                .strip_prefix("choice(")
+                .and_then(|choices| choices.strip_suffix(')'))
+                .map(|choices| {
+                    choices
+                        .split(',')
+                        .map(|choice| choice.trim().to_string())
+                        .filter(|choice| !choice.is_empty())
+                        .collect::<Vec<_>>()
+                })
+                .filter(|choices| !choices.is_empty())
+                .map(Self::Choice),
+        }
+    }
+}
+
+impl fmt::Display for FieldType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Self::Text => write!(f, "text"),
+            Self::Number => write!(f, "number"),
+            Self::Url => write!(f, "url"),
+            Self::Date => write!(f, "date"),
+            Self::Choice(ref choices) => write!(f, "choice({})", choices.join(", ")),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct TemplateField {
+    pub(crate) name: String,
+    pub(crate) field_type: FieldType,
+    pub(crate) required: bool,
+    /// Extra pattern the value has to match, on top of the type check
+    pub(crate) validator: Option<Regex>,
+}
+
+impl TemplateField {
+    pub(crate) fn new(name: &str, field_type: FieldType, required: bool) -> Self {
+        TemplateField {
+            name: name.to_string(),
+            field_type,
+            required,
+            validator: None,
+        }
+    }
+
+    /// Parses a definition line such as "Amount: number required /^\d+$/"
+    fn parse(line: &str) -> Result<Self, String> {
+        let (name, spec) = line
+            .split_once(':')
+            .ok_or_else(|| format!("\"{}\" should look like \"Name: type\"", line))?;
+        let name = name.trim();
+        if name.is_empty() {
+            return Err(format!("\"{}\" is missing a field name", line));
+        }
+        // "title" is the built-in Title field, not a field of its own
+        let name = BUILT_IN_FIELDS
+            .into_iter()
+            .find(|built_in| built_in.eq_ignore_ascii_case(name))
+            .unwrap_or(name);
+
+        let caps = FIELD_SPEC
+            .captures(spec.trim())
+            .ok_or_else(|| format!("Could not read the definition of \"{}\"", name))?;
+        let field_type = caps
+            .get(1)
+            .and_then(|field_type| FieldType::parse(field_type.as_str()))
+            .ok_or_else(|| {
+                format!(
+                    "Unknown type for \"{}\", use text, number, url, date or choice(a, b)",
+                    name
+                )
+            })?;
+        let validator = match caps.get(3) {
+            Some(pattern) => Some(
+                Regex::new(pattern.as_str())
+                    .map_err(|_| format!("Invalid validator for \"{}\"", name))?,
+            ),
+            None => None,
+        };
+
+        Ok(TemplateField {
+            name: name.to_string(),
+            field_type,
+            required: caps.get(2).is_some(),
+            validator,
+        })
+    }
+
+    /// Checks a submitted value, returning it normalized or an explanation of what is wrong.
+    /// Dates are read and written back in `timezone`
+    pub(crate) fn validate(&self, value: &str, timezone: Tz) -> Result<String, String> {
+        let value = value.trim();
+        if value.is_empty() {
+            return Err(format!("{} can't be empty", self.name));
+        }
+        if value.contains('\n') {
+            return Err(format!("{} has to fit on a single line", self.name));
+        }
+
+        let value = match self.field_type {
+            FieldType::Text => value.to_string(),
+            FieldType::Date => format_date(&parse_date(value, Utc::now(), timezone)?, timezone),
+            FieldType::Number => match value.parse::<f64>() {
+                Ok(_) => value.to_string(),
+                Err(_) => return Err(format!("{} has to be a number", self.name)),
+            },
+            FieldType::Url => match URL.is_match(value) {
+                true => value.to_string(),
+                false => return Err(format!("{} has to be a http(s) link", self.name)),
+            },
+            FieldType::Choice(ref choices) => match choices
+                .iter()
+                .find(|choice| choice.eq_ignore_ascii_case(value))
+            {
+                Some(choice) => choice.clone(),
+                None => {
+                    return Err(format!(
+                        "{} has to be one of: {}",
+                        self.name,
+                        choices.join(", ")
+                    ))
+                }
+            },
+        };
+
+        match &self.validator {
+            Some(validator) if !validator.is_match(&value) => Err(format!(
+                "{} has to match the pattern {}",
+                self.name,
+                validator.as_str()
+            )),
+            _ => Ok(value),
+        }
+    }
+
+    /// Message asking the user for this field
+    pub(crate) fn prompt(&self) -> String {
+        match self.field_type {
+            FieldType::Text => format!("Enter the proposal {}", self.name),
+            FieldType::Choice(ref choices) => format!(
+                "Enter the proposal {}, one of: {}",
+                self.name,
+                choices.join(", ")
+            ),
+            FieldType::Date => format!(
+                "Enter the proposal {}, e.g. 2024-05-01 18:00, 01/05/2024, tomorrow 18:00 or in 3 days",
+                self.name
+            ),
+            ref field_type => format!("Enter the proposal {} ({})", self.name, field_type),
+        }
+    }
+}
+
+impl fmt::Display for TemplateField {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.name, self.field_type)?;
+        if self.required {
+            write!(f, " required")?;
+        }
+        if let Some(validator) = &self.validator {
+            write!(f, " /{}/", validator.as_str())?;
+        }
+        Ok(())
+    }
+}
+
+/// The fields a proposal is made of. Chats can define their own on top of the default one
+#[derive(Debug, Clone)]
+pub(crate) struct ProposalTemplate {
+    pub(crate) name: String,
+    pub(crate) fields: Vec<TemplateField>,
+}
+
+impl Default for ProposalTemplate {
+    fn default() -> Self {
+        ProposalTemplate {
+            name: "Default".to_string(),
+            fields: vec![
+                TemplateField::new(TITLE, FieldType::Text, true),
+                TemplateField::new(DESCRIPTION, FieldType::Text, true),
+                TemplateField::new(STARTING_DATE, FieldType::Date, true),
+                TemplateField::new(EXPIRATION_DATE, FieldType::Date, true),
+                TemplateField::new(TAGS, FieldType::Text, false),
+                TemplateField::new(
+                    BALLOT,
+                    FieldType::Choice(
+                        [BALLOT_BUTTONS, BALLOT_POLL, BALLOT_ANONYMOUS_POLL]
+                            .map(String::from)
+                            .to_vec(),
+                    ),
+                    false,
+                ),
+            ],
+        }
+    }
+}
+
+impl ProposalTemplate {
+    /// Parses a template definition: the name on the first line, then one field per line.
+    /// A required Title field is added when the definition doesn't have one
+    pub(crate) fn parse(definition: &str) -> Result<Self, String> {
+        let mut lines = definition
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty());
+        let name = lines
+            .next()
+            .ok_or_else(|| "The template needs a name".to_string())?
+            .to_string();
+        // counted in bytes, like the callback data the name is sent in
+        if name.len() > MAX_TEMPLATE_NAME_LENGTH {
+            return Err(format!(
+                "Template names can be at most {} characters long, fewer with emoji or accents",
+                MAX_TEMPLATE_NAME_LENGTH
+            ));
+        }
+        // the picker finds templates by name
+        if name.eq_ignore_ascii_case(&ProposalTemplate::default().name) {
+            return Err(format!("\"{}\" is the name of the built-in template", name));
+        }
+
+        let mut fields = lines
+            .map(TemplateField::parse)
+            .collect::<Result<Vec<_>, _>>()?;
+        if fields.is_empty() {
+            return Err(format!("Template \"{}\" has no fields", name));
+        }
+        for (index, field) in fields.iter().enumerate() {
+            if fields[..index]
+                .iter()
+                .any(|other| other.name.eq_ignore_ascii_case(&field.name))
+            {
+                return Err(format!("Field \"{}\" is defined twice", field.name));
+            }
+        }
+        if !fields.iter().any(|field| field.name == TITLE) {
+            fields.insert(0, TemplateField::new(TITLE, FieldType::Text, true));
+        }
+
+        Ok(ProposalTemplate { name, fields })
+    }
+}
+
+impl fmt::Display for ProposalTemplate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name)?;
+        for field in &self.fields {
+            write!(f, "\n  {}", field)?;
+        }
+        Ok(())
+    }
+}
+
+/// Templates a chat can create proposals from: the default one first, then the chat's own
+pub(crate) fn chat_templates(chat_id: ChatId) -> Vec<ProposalTemplate> {
+    let mut templates = vec![ProposalTemplate::default()];
+    templates.extend(GLOBAL_TEMPLATE_STORAGE.get(chat_id));
+    templates
+}
+
+/// The chat's template at `index`, as long as it is still the one `draft` was started from.
+/// Admins can remove or redefine templates while members are drafting
+pub(crate) fn draft_template(draft: &Draft, index: usize) -> Result<ProposalTemplate, String> {
+    let current = chat_templates(draft.chat_id).into_iter().nth(index);
+    match (&draft.template, current) {
+        (Some(started), Some(current)) if started.to_string() == current.to_string() => Ok(current),
+        _ => Err(STALE_DRAFT.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn field(line: &str) -> TemplateField {
+        TemplateField::parse(line).unwrap()
+    }
+
+    #[test]
+    fn parses_field_definitions() {
+        let amount = field("Amount: number required /^\\d+$/");
+        assert_eq!(amount.name, "Amount");
+        assert_eq!(amount.field_type, FieldType::Number);
+        assert!(amount.required);
+        assert_eq!(
+            amount.validator.map(|re| re.as_str().to_string()),
+            Some(r"^\d+$".to_string())
+        );
+
+        let kind = field("Kind: Choice(small, , big)");
+        assert_eq!(
+            kind.field_type,
+            FieldType::Choice(vec!["small".to_string(), "big".to_string()])
+        );
+        assert!(!kind.required);
+
+        // built-in fields keep their canonical name
+        assert_eq!(field("title: text").name, TITLE);
+        assert_eq!(field("starting date: date").name, STARTING_DATE);
+    }
+
+    #[test]
+    fn rejects_invalid_field_definitions() {
+        assert!(TemplateField::parse("Amount").is_err());
+        assert!(TemplateField::parse(": text").is_err());
+        assert!(TemplateField::parse("Amount: money").is_err());
+        assert!(TemplateField::parse("Amount: choice()").is_err());
+        assert!(TemplateField::parse("Amount: number /(/").is_err());
+        assert!(TemplateField::parse("Amount: number optional").is_err());
+    }
+
+    #[test]
+    fn validates_values() {
+        let validate = |line: &str, value: &str| field(line).validate(value, Tz::UTC);
+        assert_eq!(validate("Note: text", "  hello "), Ok("hello".to_string()));
+        assert!(validate("Note: text", " ").is_err());
+        assert!(validate("Note: text", "two\nlines").is_err());
+
+        assert_eq!(validate("Amount: number", "12.5"), Ok("12.5".to_string()));
+        assert!(validate("Amount: number", "twelve").is_err());
+
+        assert!(validate("Link: url", "https://example.com/page").is_ok());
+        assert!(validate("Link: url", "ftp://example.com").is_err());
+        assert!(validate("Link: url", "https://localhost").is_err());
+
+        assert_eq!(
+            validate("Kind: choice(Small, Big)", "BIG"),
+            Ok("big".to_string())
+        );
+        assert!(validate("Kind: choice(Small, Big)", "huge").is_err());
+
+        assert_eq!(
+            validate("Due: date", "2030-01-02 03:04"),
+            Ok("2030-01-02 03:04 UTC".to_string())
+        );
+        assert!(validate("Due: date", "someday").is_err());
+
+        assert_eq!(
+            validate("Code: text /^[A-Z]{3}$/", "ABC"),
+            Ok("ABC".to_string())
+        );
+        assert!(validate("Code: text /^[A-Z]{3}$/", "abcd").is_err());
+    }
+
+    #[test]
+    fn parses_templates() {
+        let template = ProposalTemplate::parse("Budget\n\nAmount: number required\n").unwrap();
+        assert_eq!(template.name, "Budget");
+        let names = template
+            .fields
+            .iter()
+            .map(|field| field.name.as_str())
+            .collect::<Vec<_>>();
+        // a required Title is added
+        assert_eq!(names, [TITLE, "Amount"]);
+
+        let template = ProposalTemplate::parse("Budget\ntitle: text\nAmount: number").unwrap();
+        assert_eq!(template.fields.len(), 2);
+        assert!(!template.fields[0].required);
+    }
+
+    #[test]
+    fn rejects_invalid_templates() {
+        assert!(ProposalTemplate::parse("").is_err());
+        assert!(ProposalTemplate::parse("Budget").is_err());
+        assert!(ProposalTemplate::parse("Budget\nAmount: number\namount: text").is_err());
+        assert!(ProposalTemplate::parse("default\nAmount: number").is_err());
+        assert!(ProposalTemplate::parse(&format!("{}\nAmount: number", "a".repeat(33))).is_err());
+        // 11 characters of 4 bytes each don't fit
+        assert!(ProposalTemplate::parse(&format!("{}\nAmount: number", "📋".repeat(11))).is_err());
+        assert!(ProposalTemplate::parse(&format!("{}\nAmount: number", "a".repeat(32))).is_ok());
+    }
+}
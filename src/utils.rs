@@ -1,7 +1,19 @@
+use crate::consts::ADMIN_CACHE_MINUTES;
+use crate::storage::{
+    ChatAdmins, Proposal, TgAdminStorage, TgChatSettingsStorage, TgMembershipStorage,
+    GLOBAL_ADMIN_STORAGE, GLOBAL_CHAT_SETTINGS_STORAGE, GLOBAL_MEMBERSHIP_STORAGE,
+};
 use crate::TgError;
+use chrono::Utc;
 use core::time::Duration;
+use hashbrown::HashMap;
+use teloxide::payloads::{SendDocument, SendMessage, SendPoll};
 use teloxide::prelude::Requester;
-use teloxide::types::{ChatId, MessageId};
+use teloxide::requests::{HasPayload, JsonRequest, MultipartRequest};
+use teloxide::types::{
+    Chat, ChatId, ChatKind, ChatPublic, Message, MessageId, MessageKind, PublicChatKind, Recipient,
+    User, UserId,
+};
 use teloxide::Bot;
 use tokio::time::sleep;
 
@@ -21,3 +33,214 @@ pub async fn delete_previous_messages(
     }
     Ok(())
 }
+
+/// Name shown for a user in comments and on cards, falling back to the full name
+pub fn user_name(user: &User) -> String {
+    user.username.clone().unwrap_or_else(|| user.full_name())
+}
+
+/// Admins of a group chat, fetched from Telegram when the cached list is missing or stale
+pub async fn chat_admins(bot: &Bot, chat_id: ChatId) -> Result<Vec<UserId>, TgError> {
+    let now = Utc::now();
+    if let Some(cached) = GLOBAL_ADMIN_STORAGE.get(chat_id) {
+        if now - cached.fetched_at < chrono::Duration::minutes(ADMIN_CACHE_MINUTES) {
+            return Ok(cached.admins);
+        }
+    }
+    let admins = bot
+        .get_chat_administrators(chat_id)
+        .await?
+        .into_iter()
+        .map(|member| member.user.id)
+        .collect::<Vec<_>>();
+    GLOBAL_ADMIN_STORAGE.insert(
+        chat_id,
+        ChatAdmins {
+            admins: admins.clone(),
+            fetched_at: now,
+        },
+    );
+    Ok(admins)
+}
+
+/// Whether the user administers the chat. A private chat is only administered by the user it
+/// is with, so being in a private chat with the bot gives no rights over other chats
+pub async fn is_chat_admin(bot: &Bot, chat_id: ChatId, user_id: UserId) -> Result<bool, TgError> {
+    if chat_id.is_user() {
+        return Ok(chat_id.0 == user_id.0 as i64);
+    }
+    Ok(chat_admins(bot, chat_id).await?.contains(&user_id))
+}
+
+/// Whether a command was sent by an admin of the chat it was sent in. Admins posting
+/// anonymously send their messages on behalf of the chat itself
+pub async fn is_sender_admin(bot: &Bot, msg: &Message) -> Result<bool, TgError> {
+    if msg
+        .sender_chat()
+        .is_some_and(|sender| sender.id == msg.chat.id)
+    {
+        return Ok(true);
+    }
+    match msg.from() {
+        Some(user) => is_chat_admin(bot, msg.chat.id, user.id).await,
+        None => Ok(false),
+    }
+}
+
+/// Whether the user is in the chat, e.g. before showing them the chat's proposals elsewhere
+pub async fn is_chat_member(bot: &Bot, chat_id: ChatId, user_id: UserId) -> bool {
+    if chat_id.is_user() {
+        return chat_id.0 == user_id.0 as i64;
+    }
+    bot.get_chat_member(chat_id, user_id)
+        .await
+        .is_ok_and(|member| member.is_present())
+}
+
+/// The proposals from chats the user is a member of, e.g. before listing proposals of every
+/// chat in a private chat. Membership is looked up once per chat
+pub async fn in_member_chats(
+    bot: &Bot,
+    user_id: UserId,
+    proposals: Vec<Proposal>,
+) -> Vec<Proposal> {
+    let mut membership = HashMap::new();
+    let mut visible = vec![];
+    for proposal in proposals {
+        let is_member = match membership.get(&proposal.chat_id) {
+            Some(is_member) => *is_member,
+            None => {
+                let is_member = is_chat_member(bot, proposal.chat_id, user_id).await;
+                membership.insert(proposal.chat_id, is_member);
+                is_member
+            }
+        };
+        if is_member {
+            visible.push(proposal);
+        }
+    }
+    visible
+}
+
+/// Checks that the user may vote or propose in the chat: they have to be a current member,
+/// and a member for at least the chat's minimum number of days. `action` completes
+/// "Only members can ..."
+pub async fn check_eligibility(
+    bot: &Bot,
+    chat_id: ChatId,
+    user_id: UserId,
+    action: &str,
+) -> Result<(), String> {
+    if !is_chat_member(bot, chat_id, user_id).await {
+        return Err(format!("Only members of the chat can {}", action));
+    }
+    let days = GLOBAL_CHAT_SETTINGS_STORAGE
+        .get(chat_id)
+        .min_membership_days;
+    // joins are only recorded in memory from chat_member updates, which reach admin bots only,
+    // so members who joined before the bot saw them count as around long enough
+    match GLOBAL_MEMBERSHIP_STORAGE.get(chat_id, user_id) {
+        Some(joined_at)
+            if days > 0 && Utc::now() - joined_at < chrono::Duration::days(days.into()) =>
+        {
+            Err(format!(
+                "Only members who joined at least {} day(s) ago can {}",
+                days, action
+            ))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Looks up the channel a chat links to, e.g. "@announcements" or "-1001234567890", and checks
+/// that the bot can post there and that the user administers it
+pub async fn resolve_channel(bot: &Bot, text: &str, user: UserId) -> Result<Chat, String> {
+    let recipient = match text.parse::<i64>() {
+        Ok(id) => Recipient::Id(ChatId(id)),
+        Err(_) if text.starts_with('@') => Recipient::ChannelUsername(text.to_string()),
+        Err(_) => {
+            return Err(
+                "Usage: /channel @channel_name, /channel <channel id> or /channel off".to_string(),
+            )
+        }
+    };
+    let channel = bot
+        .get_chat(recipient)
+        .await
+        .map_err(|_| format!("Could not find {}, add the bot to it first", text))?;
+    if !channel.is_channel() {
+        return Err(format!("{} is not a channel", text));
+    }
+
+    let me = bot.get_me().await.map_err(|err| err.to_string())?;
+    let can_post = bot
+        .get_chat_member(channel.id, me.id)
+        .await
+        .is_ok_and(|member| member.can_post_messages());
+    if !can_post {
+        return Err(format!(
+            "Make the bot an admin of {} who can post messages",
+            text
+        ));
+    }
+    let is_admin = bot
+        .get_chat_member(channel.id, user)
+        .await
+        .is_ok_and(|member| member.is_privileged());
+    if !is_admin {
+        return Err(format!("Only admins of {} can link it", text));
+    }
+    Ok(channel)
+}
+
+/// Forum topic the message was sent in, `None` for chats without topics and the General topic
+pub fn topic(msg: &Message) -> Option<i32> {
+    match &msg.kind {
+        MessageKind::Common(common) if common.is_topic_message => msg.thread_id,
+        _ => None,
+    }
+}
+
+/// Whether the chat is a supergroup with topics enabled
+pub async fn is_forum(bot: &Bot, chat_id: ChatId) -> bool {
+    if chat_id.is_user() {
+        return false;
+    }
+    match bot.get_chat(chat_id).await {
+        Ok(Chat {
+            kind:
+                ChatKind::Public(ChatPublic {
+                    kind: PublicChatKind::Supergroup(supergroup),
+                    ..
+                }),
+            ..
+        }) => supergroup.is_forum,
+        _ => false,
+    }
+}
+
+/// Sends into a forum topic, or to the chat itself for `None`
+pub trait InTopic {
+    fn in_topic(self, topic: Option<i32>) -> Self;
+}
+
+impl InTopic for JsonRequest<SendMessage> {
+    fn in_topic(mut self, topic: Option<i32>) -> Self {
+        self.payload_mut().message_thread_id = topic;
+        self
+    }
+}
+
+impl InTopic for JsonRequest<SendPoll> {
+    fn in_topic(mut self, topic: Option<i32>) -> Self {
+        self.payload_mut().message_thread_id = topic;
+        self
+    }
+}
+
+impl InTopic for MultipartRequest<SendDocument> {
+    fn in_topic(mut self, topic: Option<i32>) -> Self {
+        self.payload_mut().message_thread_id = topic;
+        self
+    }
+}
@@ -0,0 +1,233 @@
+use crate::TgError;
+use std::env;
+use std::net::SocketAddr;
+use teloxide::{
+    payloads::SetWebhookSetters,
+    prelude::Requester,
+    types::AllowedUpdate,
+    update_listeners::webhooks::{axum_no_setup, Options},
+    Bot,
+};
+
+/// Path updates are posted to when TG_BOT_WEBHOOK_PATH isn't set
+const DEFAULT_WEBHOOK_PATH: &str = "/webhook";
+
+/// Update kinds the handler tree reacts to. Telegram leaves chat member updates out unless
+/// they are asked for, and unlike polling a webhook is not told what the dispatcher needs
+const ALLOWED_UPDATES: [AllowedUpdate; 7] = [
+    AllowedUpdate::Message,
+    AllowedUpdate::CallbackQuery,
+    AllowedUpdate::InlineQuery,
+    AllowedUpdate::ChatMember,
+    AllowedUpdate::MyChatMember,
+    AllowedUpdate::PollAnswer,
+    AllowedUpdate::Poll,
+];
+
+/// Settings of the embedded HTTP listener, read from the environment:
+/// - TG_BOT_WEBHOOK_ADDR, the address to listen on, e.g. 0.0.0.0:8443. Webhook mode is only
+///   used when it is set
+/// - TG_BOT_WEBHOOK_PATH, the path updates are posted to, "/webhook" by default
+/// - TG_BOT_WEBHOOK_SECRET, the token Telegram sends in X-Telegram-Bot-Api-Secret-Token
+/// - TG_BOT_WEBHOOK_URL, the public https address the listener is reached at, e.g.
+///   https://bot.example.com. It needs TG_BOT_WEBHOOK_SECRET, or anyone who finds the URL
+///   could post updates. Without it the webhook is not registered with Telegram, so updates
+///   can be posted to the listener by hand
+#[derive(Clone, Debug)]
+pub(crate) struct WebhookConfig {
+    pub(crate) address: SocketAddr,
+    pub(crate) path: String,
+    pub(crate) secret: Option<String>,
+    pub(crate) public_url: Option<String>,
+}
+
+impl WebhookConfig {
+    /// The webhook settings, `None` when the bot should use long polling
+    pub(crate) fn from_env() -> Option<Result<Self, String>> {
+        let address = env::var("TG_BOT_WEBHOOK_ADDR").ok()?;
+        Some(Self::parse(
+            &address,
+            env::var("TG_BOT_WEBHOOK_PATH").ok(),
+            env::var("TG_BOT_WEBHOOK_SECRET").ok(),
+            env::var("TG_BOT_WEBHOOK_URL").ok(),
+        ))
+    }
+
+    fn parse(
+        address: &str,
+        path: Option<String>,
+        secret: Option<String>,
+        public_url: Option<String>,
+    ) -> Result<Self, String> {
+        let address = address
+            .parse::<SocketAddr>()
+            .map_err(|_| format!("\"{}\" is not an address like 0.0.0.0:8443", address))?;
+        let path = match path.as_deref().map(str::trim) {
+            None | Some("") => DEFAULT_WEBHOOK_PATH.to_string(),
+            Some(path) if path.starts_with('/') => path.to_string(),
+            Some(path) => format!("/{}", path),
+        };
+        // Telegram only accepts 1-256 letters, digits, _ and -
+        let secret = secret.filter(|secret| !secret.is_empty());
+        if let Some(secret) = &secret {
+            if secret.len() > 256
+                || !secret
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+            {
+                return Err(
+                    "The webhook secret can only have up to 256 letters, digits, _ and -"
+                        .to_string(),
+                );
+            }
+        }
+        let public_url = public_url
+            .map(|url| url.trim().trim_end_matches('/').to_string())
+            .filter(|url| !url.is_empty());
+        if public_url.is_some() && secret.is_none() {
+            return Err(
+                "TG_BOT_WEBHOOK_URL needs a TG_BOT_WEBHOOK_SECRET, so that only Telegram can post updates"
+                    .to_string(),
+            );
+        }
+        Ok(WebhookConfig {
+            address,
+            path,
+            secret,
+            public_url,
+        })
+    }
+}
+
+/// Starts the HTTP listener and registers it with Telegram when a public address is
+/// configured. Returns the update listener for the dispatcher and whether the webhook was
+/// registered, so it can be removed on shutdown
+pub(crate) async fn webhook_listener(
+    bot: &Bot,
+    config: WebhookConfig,
+) -> Result<
+    (
+        impl teloxide::update_listeners::UpdateListener<Err = std::convert::Infallible>,
+        bool,
+    ),
+    TgError,
+> {
+    let local_url = format!("http://{}{}", config.address, config.path);
+    let mut options = Options::new(
+        config.address,
+        local_url
+            .parse()
+            .map_err(|err| TgError::Parse(format!("{}: {}", local_url, err)))?,
+    );
+    options.secret_token = config.secret.clone();
+    let (listener, stop, router) = axum_no_setup(options);
+
+    let server = axum::Server::try_bind(&config.address)
+        .map_err(|err| TgError::AnyhowError(anyhow::anyhow!(err)))?
+        .serve(router.into_make_service())
+        .with_graceful_shutdown(stop);
+    tokio::spawn(async move {
+        if let Err(err) = server.await {
+            log::error!("the webhook listener stopped: {}", err);
+        }
+    });
+    log::info!("Listening for updates on {}", local_url);
+
+    let Some(public_url) = config.public_url else {
+        log::info!("TG_BOT_WEBHOOK_URL is not set, the webhook is not registered with Telegram");
+        return Ok((listener, false));
+    };
+    let url = format!("{}{}", public_url, config.path);
+    let mut request = bot.set_webhook(
+        url.parse()
+            .map_err(|err| TgError::Parse(format!("{}: {}", url, err)))?,
+    );
+    request = request.allowed_updates(ALLOWED_UPDATES);
+    if let Some(secret) = config.secret {
+        request = request.secret_token(secret);
+    }
+    request.await?;
+    log::info!("Registered the webhook {}", url);
+    Ok((listener, true))
+}
+
+/// Removes the webhook from Telegram once the bot stops
+pub(crate) async fn unregister_webhook(bot: &Bot) {
+    match bot.delete_webhook().await {
+        Ok(_) => log::info!("Removed the webhook"),
+        Err(err) => log::warn!("could not remove the webhook: {}", err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(
+        address: &str,
+        path: Option<&str>,
+        secret: Option<&str>,
+        public_url: Option<&str>,
+    ) -> Result<WebhookConfig, String> {
+        WebhookConfig::parse(
+            address,
+            path.map(String::from),
+            secret.map(String::from),
+            public_url.map(String::from),
+        )
+    }
+
+    #[test]
+    fn reads_the_address() {
+        let config = parse("0.0.0.0:8443", None, None, None).unwrap();
+        assert_eq!(config.address, "0.0.0.0:8443".parse().unwrap());
+        assert!(parse("localhost", None, None, None).is_err());
+        assert!(parse("0.0.0.0", None, None, None).is_err());
+    }
+
+    #[test]
+    fn normalizes_the_path() {
+        let path = |path| parse("127.0.0.1:80", path, None, None).unwrap().path;
+        assert_eq!(path(None), DEFAULT_WEBHOOK_PATH);
+        assert_eq!(path(Some(" ")), DEFAULT_WEBHOOK_PATH);
+        assert_eq!(path(Some("/updates")), "/updates");
+        assert_eq!(path(Some("updates")), "/updates");
+    }
+
+    #[test]
+    fn checks_the_secret() {
+        let secret =
+            |secret: &str| parse("127.0.0.1:80", None, Some(secret), None).map(|c| c.secret);
+        assert_eq!(secret("abc_DEF-123"), Ok(Some("abc_DEF-123".to_string())));
+        assert_eq!(secret(""), Ok(None));
+        assert!(secret("not secret").is_err());
+        assert!(secret("é").is_err());
+        assert!(secret(&"a".repeat(257)).is_err());
+        assert!(secret(&"a".repeat(256)).is_ok());
+    }
+
+    #[test]
+    fn requires_a_secret_for_a_public_webhook() {
+        assert!(parse("0.0.0.0:8443", None, None, Some("https://bot.example.com")).is_err());
+        assert!(parse(
+            "0.0.0.0:8443",
+            None,
+            Some(""),
+            Some("https://bot.example.com")
+        )
+        .is_err());
+        let config = parse(
+            "0.0.0.0:8443",
+            None,
+            Some("token"),
+            Some(" https://bot.example.com/ "),
+        )
+        .unwrap();
+        assert_eq!(
+            config.public_url.as_deref(),
+            Some("https://bot.example.com")
+        );
+        // the local listener can do without, nothing points Telegram at it
+        assert!(parse("127.0.0.1:8443", None, None, Some(" ")).is_ok());
+    }
+}